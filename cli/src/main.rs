@@ -12,19 +12,76 @@
 //!  filter \
 //!  --file "../sdk/test/example/data.csv"
 //! ```
+//! Without `--output-path` the results are written to stdout, status messages being written to stderr
+//! ```bash
+//! cli transform --where "val == \"'1'\"" filter --file "../sdk/test/example/data.csv" | head
+//! ```
+//! Use `--file -` to read from stdin
+//! ```bash
+//! zcat big.csv.gz | cli transform --query 1 --column key filter --file - > filtered.csv
+//! ```
+//! Filter the files of a glob pattern on 8 threads, keeping the records in the order of the files
+//! ```bash
+//! cli transform --query 1 --column key --threads 8 --preserve-order filter --file "data/2024-*.csv" > filtered.csv
+//! ```
+//! A single large file is split into byte ranges filtered concurrently, the records keeping the order of the file
+//! ```bash
+//! cli transform --query 1 --column key --threads 0 filter --file big.csv > filtered.csv
+//! ```
+//! Add `--mmap` to memory map an uncompressed file, the records being filtered without copying them
+//! ```bash
+//! cli transform --query 1 --column key filter --file big.csv --mmap > filtered.csv
+//! ```
+//! Write the `name` column first, then the `addr_` columns and the columns 5 to 7, leaving out `addr_zip`
+//! ```bash
+//! cli transform --where "age >= 30" --select 'name,/^addr_/,$5-$7' --drop addr_zip filter --file data.csv
+//! ```
+//! Rename a column and add computed columns in the same pass as the filter
+//! ```bash
+//! cli transform --where "qty > 0" --rename qty=quantity --with 'total=price*quantity' --with 'year=year(ordered_at)' filter --file orders.csv
+//! ```
+//! Sort the files of a glob pattern by state, then by descending age, spilling sorted runs to disk past 512 MiB
+//! ```bash
+//! cli sort --by state,age:desc --nulls first --memory-limit 512M --file "data/2024-*.csv" --column-types string,int > sorted.csv
+//! ```
+//! Infer the column types of an undocumented file, the printed `--column-types` can be passed back to `filter`
+//! ```bash
+//! cli schema --file "../sdk/test/example/data.csv" --sample 1000
+//! ```
+//! Add `--report json` to print a machine readable report of the run to stderr
+//! ```bash
+//! cli transform --query 1 --column key --output-path out.csv filter --file data.csv --report json
+//! {"error":null,"exit_code":0,"output_path":"out.csv","rows_matched":1,"rows_read":3,"status":"success"}
+//! ```
+//! The report is printed for arguments that do not parse too, as an `invalid_argument` error with the exit code `2`
 //! # Errors
 //! This function will return an error if the command is not recognized or if there is an issue with the filtering operations.
+//! The exit code tells the class of the error apart:
 //! 
-use clap::Parser;
-use sdk::data::manipulation;
-use sdk;
-
+//! | Code | Error |
+//! |------|-------|
+//! | 0 | Success |
+//! | 2 | Bad arguments, e.g. an unknown flag, column type or an invalid `--where` expression |
+//! | 3 | I/O, e.g. a missing file or a glob pattern matching no file |
+//! | 4 | Schema mismatch, a missing column or files with different headers |
+//! | 5 | Malformed data, a broken record or a field not matching its column type |
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use sdk::data::{inference, manipulation, parallel, sort};
 
-/// Cli enum to hold the different commands
-/// 
+/// Exit code of bad arguments, the code used by clap for usage errors
+const EXIT_BAD_ARGS: i32 = 2;
+/// Exit code of I/O errors
+const EXIT_IO: i32 = 3;
+/// Exit code of schema mismatches
+const EXIT_SCHEMA: i32 = 4;
+/// Exit code of malformed data
+const EXIT_MALFORMED: i32 = 5;
 
+/// Command line arguments, the [Cli] command and the global options
 #[derive(Parser)]
 #[clap(
+    version,
     about = "
     ░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░\n\
     CLI tool to read csv files and perform filtering operations\n\
@@ -37,52 +94,185 @@ use sdk;
     Developer: @stevenloiaza\n\
     ░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░░"
 )]
+struct Args {
+    #[clap(long, value_enum, global = true, help = "Print a report of the run to stderr")]
+    report: Option<ReportFormat>,
+    #[clap(subcommand)]
+    command: Cli,
+}
+
+/// Formats of the `--report`
+#[derive(ValueEnum, Clone, Copy)]
+enum ReportFormat {
+    Json,
+}
+
+/// Cli enum to hold the different commands
+/// 
+
+#[derive(Subcommand)]
 enum Cli {
     /// CLI Transfrom entry point for CSV I/O operations, specifically filtering.
     /// See [Command]() for more information
     #[clap(about = "CSV I/O operations, specifically filtering")]
-    Transform(manipulation::Command),
+    Transform(Box<manipulation::Command>),
+    /// CLI Schema entry point inferring column types, nullability and min/max of a CSV file.
+    /// See [SchemaCommand]() for more information
+    #[clap(about = "Infer the schema of a CSV file")]
+    Schema(inference::SchemaCommand),
+    /// CLI Sort entry point ordering the records of a CSV file, or of the files of a glob pattern, by some columns.
+    /// See [SortCommand]() for more information
+    #[clap(about = "Sort a CSV file by one or more columns")]
+    Sort(sort::SortCommand),
     /// Placeholder for additional cli commands
     #[clap(about = "Placeholder for additional cli commands")]
     Placeholder
 }
 
+/// Rows counted by a successful command
+struct Rows {
+    read: usize,
+    matched: Option<usize>,
+}
+
+/// Exit code of the class of `error`, see the table of the module documentation
+fn exit_code(error: &sdk::Error) -> i32 {
+    match error {
+        sdk::Error::InvalidArgument(_) | sdk::Error::Expression(_) => EXIT_BAD_ARGS,
+        sdk::Error::Io(_) | sdk::Error::Glob(_) => EXIT_IO,
+        sdk::Error::MissingColumn(_) | sdk::Error::HeaderMismatch { .. } => EXIT_SCHEMA,
+        sdk::Error::MalformedRecord { .. } | sdk::Error::TypeConversion(_) => EXIT_MALFORMED,
+    }
+}
+
+/// Kind, message and position of `error` for the json report
+fn error_details(error: &sdk::Error) -> Value {
+    let mut details = json!({"kind": error.name(), "message": error.to_string()});
+    match error {
+        sdk::Error::MissingColumn(column) => details["column"] = json!(column),
+        sdk::Error::HeaderMismatch { file, expected, found } => {
+            details["file"] = json!(file);
+            details["expected"] = json!(expected);
+            details["found"] = json!(found);
+        }
+        sdk::Error::MalformedRecord { line, byte, .. } => {
+            details["line"] = json!(line);
+            details["byte"] = json!(byte);
+        }
+        sdk::Error::TypeConversion(e) => {
+            details["row"] = json!(e.row);
+            details["column"] = json!(e.column);
+            details["value"] = json!(e.value);
+            details["column_type"] = json!(e.column_type.name());
+        }
+        _ => {}
+    }
+    details
+}
+
+/// Json report of a run, see the `--report` option
+fn report(code: i32, rows: Option<&Rows>, output_path: Option<String>, error: Option<&sdk::Error>) -> Value {
+    json!({
+        "status": if error.is_none() { "success" } else { "error" },
+        "exit_code": code,
+        "rows_read": rows.map(|rows| rows.read),
+        "rows_matched": rows.and_then(|rows| rows.matched),
+        "output_path": output_path,
+        "error": error.map(error_details),
+    })
+}
+
+/// Parses the command line, exiting with [EXIT_BAD_ARGS] on a usage error
+///
+/// The arguments did not parse, so `--report json` is looked up in the raw arguments. `--help` and `--version` still
+/// print to stdout and exit with `0`.
+fn parse_args() -> Args {
+    let e = match Args::try_parse() {
+        Ok(args) => return args,
+        Err(e) => e,
+    };
+    let raw: Vec<String> = std::env::args_os().map(|arg| arg.to_string_lossy().into_owned()).collect();
+    let json = raw.iter().any(|arg| arg == "--report=json") || raw.windows(2).any(|pair| pair[0] == "--report" && pair[1] == "json");
+    if !e.use_stderr() || !json {
+        e.exit();
+    }
+    let message = e.to_string();
+    let error = sdk::Error::InvalidArgument(message.trim_start_matches("error: ").trim_end().to_string());
+    eprintln!("{}", report(EXIT_BAD_ARGS, None, None, Some(&error)));
+    std::process::exit(EXIT_BAD_ARGS);
+}
+
 /// Main entry point for the CLI tool to read csv files and perform filtering operations
 /// # Arguments
-/// * `args` - [Cli] enum to hold the different commands
-/// # Panics
-/// CLI entry point will panic if the command is not recognized. Additionally, it will 
-/// inherit panics from the filtering functions
-
+/// * `args` - [Args] holding the [Cli] command and the `--report` format
+/// # Errors
+/// The [sdk::Error] of an unrecognized command or a failed operation is printed to stderr and the process exits with
+/// the code of its class, see [exit_code]
 fn main() {
-    let args = Cli::parse();
-    let results: Result<String, std::io::Error> = match args {
+    let args = parse_args();
+    let output_path = match &args.command {
+        Cli::Transform(filter) => filter.output_path.clone(),
+        Cli::Sort(command) => command.output_path.clone(),
+        _ => None,
+    };
+    let results: Result<Rows, sdk::Error> = match args.command {
         Cli::Transform(filter) => {
             let val = &filter.subcommand;
-            let response = match val {
+            match val {
+                manipulation::Subcommand::Filter(csv) | manipulation::Subcommand::Anyfilter(csv)
+                    if filter.threads.is_some() => {
+                    parallel::filter(&filter, csv)
+                },
                 manipulation::Subcommand::Filter(csv) => {
-                    manipulation::filtering::filter(
-                        sdk::loader(&csv),
+                    sdk::loader(csv).and_then(|buffer| manipulation::filtering::filter(
+                        buffer,
                         &filter,
-                        &csv
-                    )
+                        csv
+                    ))
                 },
                 manipulation::Subcommand::Anyfilter(csv) => {
-                    manipulation::filtering::any_filter(
-                        sdk::loader(&csv),
+                    sdk::loader(csv).and_then(|buffer| manipulation::filtering::any_filter(
+                        buffer,
                         &filter,
-                        &csv
-                    )
+                        csv
+                    ))
                 }
-            };
-
-            response
+            }.map(|summary| Rows { read: summary.rows_read, matched: Some(summary.rows_matched) })
+        }
+        Cli::Schema(command) => {
+            sdk::loader(&command.csv)
+                .and_then(|buffer| inference::infer_schema(buffer, &command.csv, command.sample))
+                .map(|schema| {
+                    println!("{}", schema);
+                    Rows { read: schema.rows, matched: None }
+                })
+        }
+        Cli::Sort(command) => {
+            sdk::loader(&command.csv)
+                .and_then(|buffer| sort::sort(buffer, &command))
+                .map(|rows| Rows { read: rows, matched: None })
         }
-        _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "Unknown command")),
+        _ => Err(sdk::Error::InvalidArgument("Unknown command".to_string())),
     };
 
-    match results {
-        Ok(_) => {println!("\x1b[0;32mSuccess\x1b[0m")},
-        Err(e) => {println!("\x1b[0;31mError\x1b[0m {}", e)},
+    // The reader of a pipe, e.g. `head`, exited before the end of the output
+    let results = match results {
+        Err(sdk::Error::Io(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(None),
+        results => results.map(Some),
+    };
+    let code = results.as_ref().err().map(exit_code).unwrap_or(0);
+
+    // Status messages go to stderr, stdout only carries the csv output
+    match args.report {
+        Some(ReportFormat::Json) => {
+            let rows = results.as_ref().ok().and_then(Option::as_ref);
+            eprintln!("{}", report(code, rows, output_path, results.as_ref().err()));
+        }
+        None => match &results {
+            Ok(Some(_)) => {eprintln!("\x1b[0;32mSuccess\x1b[0m")},
+            Ok(None) => {},
+            Err(e) => {eprintln!("\x1b[0;31mError\x1b[0m {}", e)},
+        },
     }
+    std::process::exit(code);
 }
@@ -0,0 +1,121 @@
+//! Throughput of the structural byte [Scanner] kernels and of the record tokenizer
+//!
+//! Measures 64 MiB of generated csv data, or the csv file given as argument:
+//! ```bash
+//! cargo bench -p sdk --bench scanner
+//! cargo bench -p sdk --bench scanner -- /data/sample.csv
+//! ```
+//! The tokenizer is compared with the character by character splitting it replaced.
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+use sdk::data::mapped::{MappedFile, RecordSlices};
+use sdk::data::scanner::{Kernel, Scanner};
+use sdk::data::tokenizer::{self, QUOTE};
+
+/// Runs of each measure, the fastest one is reported
+const RUNS: usize = 5;
+
+fn main() {
+    let path = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let mapped = path.as_ref().map(|path| MappedFile::open(path).expect("Unable to map the sample file"));
+    let generated = match &mapped {
+        Some(_) => Vec::new(),
+        None => generate(64 << 20),
+    };
+    let data = mapped.as_ref().map_or(&generated[..], MappedFile::as_bytes);
+    println!("{} MiB of {}", data.len() >> 20, path.as_deref().unwrap_or("generated data"));
+
+    let mut portable = None;
+    for kernel in [Kernel::Portable, Kernel::Sse2, Kernel::Avx2] {
+        let Some(scanner) = Scanner::with_kernel(&[b',', QUOTE as u8, b'\n'], kernel) else {
+            println!("scan      {:<10} not supported", kernel.name());
+            continue;
+        };
+        let elapsed = measure(|| scanner.count(data));
+        let baseline = *portable.get_or_insert(elapsed);
+        report("scan", kernel.name(), data.len(), elapsed, baseline);
+    }
+
+    let baseline = measure(|| tokenize(data, |record| reference_split(record).len()));
+    report("tokenize", "reference", data.len(), baseline, baseline);
+    let elapsed = measure(|| tokenize(data, |record| tokenizer::split_record(record, ',').map_or(0, |fields| fields.len())));
+    report("tokenize", Kernel::detect().name(), data.len(), elapsed, baseline);
+    let mut borrowed: Vec<Cow<str>> = Vec::new();
+    let elapsed = measure(|| {
+        let mut fields = 0;
+        for record in RecordSlices::new(data, ',').flatten() {
+            if record.fields_into(&mut borrowed).is_ok() {
+                fields += borrowed.len();
+            }
+        }
+        fields
+    });
+    report("tokenize", "borrowed", data.len(), elapsed, baseline);
+}
+
+/// Splits every record of `data` with `split`, returning the number of fields
+fn tokenize(data: &[u8], split: impl Fn(&str) -> usize) -> usize {
+    RecordSlices::new(data, ',').flatten().map(|record| split(record.as_str())).sum()
+}
+
+/// Fastest of [RUNS] runs of `f`
+fn measure<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(measure: &str, name: &str, bytes: usize, elapsed: Duration, baseline: Duration) {
+    let throughput = bytes as f64 / elapsed.as_secs_f64() / (1 << 30) as f64;
+    let speedup = baseline.as_secs_f64() / elapsed.as_secs_f64();
+    println!("{:<9} {:<10} {:>7.2} GiB/s {:>6.2}x", measure, name, throughput, speedup);
+}
+
+/// Csv records mixing plain, quoted and escaped fields
+fn generate(size: usize) -> Vec<u8> {
+    let mut data = b"id,name,email,note,amount\n".to_vec();
+    let mut id = 0;
+    while data.len() < size {
+        let note = match id % 4 {
+            0 => "\"multi\nline, note\"".to_string(),
+            1 => "\"say \"\"hi\"\"\"".to_string(),
+            _ => "plain text without any quote in it".to_string(),
+        };
+        data.extend(format!("{},customer {},customer{}@example.com,{},{}.{:02}\n", id, id, id, note, id * 7, id % 100).bytes());
+        id += 1;
+    }
+    data
+}
+
+/// Character by character splitting on commas, the tokenizer before the [Scanner]
+fn reference_split(record: &str) -> Vec<String> {
+    let (mut fields, mut field) = (Vec::new(), String::new());
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == QUOTE && chars.peek() == Some(&QUOTE) {
+                field.push(QUOTE);
+                chars.next();
+            } else if c == QUOTE {
+                in_quotes = false;
+            } else {
+                field.push(c);
+            }
+        } else if c == ',' {
+            fields.push(std::mem::take(&mut field));
+        } else if c == QUOTE && field.trim().is_empty() {
+            field.clear();
+            in_quotes = true;
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+    fields
+}
@@ -0,0 +1,237 @@
+//! Provides [Compression] detection, on the fly decompression of csv inputs and the compressed output [Encoder]
+//!
+//! The compression of a file is detected from its extension (`data.csv.gz`), falling back to the magic bytes at the
+//! start of the data, so `zcat`-free pipelines and misnamed files are read transparently.
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use crate::Error;
+
+/// Supported compression formats
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Detects the compression from the extension of `path`, `None` if the extension is not a compression format
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Compression> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    /// Detects the compression from the magic bytes at the start of the data
+    pub fn from_magic(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Wraps `reader` in the decoder of the format
+    pub fn decoder<R: Read + 'static>(&self, reader: R) -> Result<Box<dyn Read>, Error> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+
+    /// Wraps `writer` in the encoder of the format, using the default compression level
+    pub fn encoder<W: Write>(&self, writer: W) -> Result<Encoder<W>, Error> {
+        Ok(match self {
+            Compression::None => Encoder::None(writer),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+            Compression::Bzip2 => Encoder::Bzip2(BzEncoder::new(writer, bzip2::Compression::default())),
+            Compression::Xz => Encoder::Xz(XzEncoder::new(writer, 6)),
+        })
+    }
+}
+
+/// Writer compressing the data of a [Compression] format
+///
+/// The compressed stream is only complete once [Encoder::finish] is called.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Bzip2(BzEncoder<W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the end of the compressed stream and returns the underlying writer
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(match self {
+            Encoder::None(writer) => writer,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+            Encoder::Bzip2(encoder) => encoder.finish()?,
+            Encoder::Xz(encoder) => encoder.finish()?,
+        })
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::None(writer) => writer,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+            Encoder::Bzip2(encoder) => encoder,
+            Encoder::Xz(encoder) => encoder,
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+/// Length of the longest magic bytes, those of xz
+const MAGIC_LEN: u64 = 6;
+
+/// Wraps `reader` in a decoder chosen from the magic bytes of the data
+pub fn decompress<R: Read + 'static>(reader: R) -> Result<Box<dyn Read>, Error> {
+    let mut reader = BufReader::new(reader);
+    // Pipes may return fewer bytes than the magic bytes per read
+    let mut magic = Vec::new();
+    (&mut reader).take(MAGIC_LEN).read_to_end(&mut magic)?;
+    let compression = Compression::from_magic(&magic);
+    compression.decoder(io::Cursor::new(magic).chain(reader))
+}
+
+/// Opens the file at `path`, decompressing it on the fly when it is compressed
+///
+/// # Example
+/// ```no_run
+/// # use std::io::Read;
+/// let mut csv = String::new();
+/// sdk::compression::open("extract.csv.gz").unwrap().read_to_string(&mut csv).unwrap();
+/// ```
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>, Error> {
+    let file = File::open(&path).map_err(Error::file(&path))?;
+    match Compression::from_path(&path) {
+        Some(compression) => compression.decoder(file),
+        None => decompress(file),
+    }
+}
+
+/// Removes the compression extension of a file name, e.g. `data.csv.gz` -> `data.csv`
+pub fn strip_extension(file: &str) -> &str {
+    match Compression::from_path(file) {
+        Some(_) => file.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file),
+        None => file,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    const DATA: &str = "key,val\n1,a\n2,b\n";
+
+    fn read(reader: Box<dyn Read>) -> String {
+        let mut buffer = String::new();
+        BufReader::new(reader).read_to_string(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_decompress_magic() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(DATA.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(Compression::from_magic(&gzip), Compression::Gzip);
+        assert_eq!(read(decompress(Cursor::new(gzip)).unwrap()), DATA);
+
+        let zstd = zstd::encode_all(DATA.as_bytes(), 0).unwrap();
+        assert_eq!(read(decompress(Cursor::new(zstd)).unwrap()), DATA);
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(DATA.as_bytes()).unwrap();
+        assert_eq!(read(decompress(Cursor::new(bzip2.finish().unwrap())).unwrap()), DATA);
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(DATA.as_bytes()).unwrap();
+        assert_eq!(read(decompress(Cursor::new(xz.finish().unwrap())).unwrap()), DATA);
+
+        assert_eq!(read(decompress(Cursor::new(DATA)).unwrap()), DATA);
+    }
+
+    /// Reader returning a single byte per read, like a slow pipe
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = buf.len().min(1);
+            self.0.read(&mut buf[..end])
+        }
+    }
+
+    #[test]
+    fn test_decompress_short_reads() {
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(DATA.as_bytes()).unwrap();
+        assert_eq!(read(decompress(Trickle(Cursor::new(xz.finish().unwrap()))).unwrap()), DATA);
+        let zstd = zstd::encode_all(DATA.as_bytes(), 0).unwrap();
+        assert_eq!(read(decompress(Trickle(Cursor::new(zstd))).unwrap()), DATA);
+        assert_eq!(read(decompress(Trickle(Cursor::new(DATA.into()))).unwrap()), DATA);
+        assert_eq!(read(decompress(Trickle(Cursor::new(b"k\n".to_vec()))).unwrap()), "k\n");
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd, Compression::Bzip2, Compression::Xz] {
+            let mut encoder = compression.encoder(Vec::new()).unwrap();
+            encoder.write_all(DATA.as_bytes()).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(Compression::from_magic(&compressed), compression);
+            assert_eq!(read(decompress(Cursor::new(compressed)).unwrap()), DATA);
+        }
+    }
+
+    #[test]
+    fn test_extensions() {
+        assert_eq!(Compression::from_path("a/data.csv.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path("data.csv"), None);
+        assert_eq!(strip_extension("data.csv.gz"), "data.csv");
+        assert_eq!(strip_extension("data.csv"), "data.csv");
+    }
+}
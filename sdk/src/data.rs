@@ -1,2 +1,13 @@
-//! Data structures and functions for working with data. This module contains the following submodules [manipulation].
-pub mod manipulation;
\ No newline at end of file
+//! Data structures and functions for working with data. This module contains the following submodules [manipulation], [computed], [mapped], [parallel], [projection], [scanner], [schema], [sort], [inference], [expression], [matching] and [tokenizer].
+pub mod computed;
+pub mod expression;
+pub mod inference;
+pub mod manipulation;
+pub mod mapped;
+pub mod matching;
+pub mod parallel;
+pub mod projection;
+pub mod scanner;
+pub mod schema;
+pub mod sort;
+pub mod tokenizer;
\ No newline at end of file
@@ -0,0 +1,240 @@
+//! Renamed and computed columns of the `--rename` and `--with` options
+//!
+//! A [Derivation] renames header columns then evaluates an [expression](super::expression) per computed column on
+//! every written record, so light feature engineering happens in the same pass as the filtering. The steps run in the
+//! order of the options: the expressions see the renamed columns and the columns computed before them, and
+//! `--select` / `--drop` see the final header. A computed column named like an existing column replaces its values,
+//! and the later expressions read the computed columns as strings whatever their `--column-types`.
+//! The `--where` clause and the column queries still refer to the columns of the input.
+//!
+//! # Example
+//! ```
+//! use std::borrow::Cow;
+//! use sdk::data::{computed::Derivation, schema::Schema};
+//! let rename = vec!["qty=quantity".to_string()];
+//! let with = vec!["total=price*quantity".to_string(), "name=upper(name)".to_string()];
+//! let mut derivation = Derivation::parse(&rename, &with).unwrap().unwrap();
+//! let header = vec!["name".to_string(), "price".to_string(), "qty".to_string()];
+//! let header = derivation.resolve(Some(&header)).unwrap();
+//! assert_eq!(header.unwrap(), vec!["name", "price", "quantity", "total"]);
+//! let record = [Cow::Borrowed("ada"), Cow::Borrowed("2.5"), Cow::Borrowed("4")];
+//! let record = derivation.apply(&record, &Schema::default(), 2).unwrap();
+//! assert_eq!(record, vec!["ADA", "2.5", "4", "10"]);
+//! ```
+use std::borrow::Cow;
+use std::io::Write;
+use super::expression::{self, Expr};
+use super::manipulation::Command;
+use super::projection::{self, Projection};
+use super::schema::{ColumnType, Schema, Value};
+use super::super::reader::CsvMetadata;
+use super::super::writer::CsvWriter;
+use crate::Error;
+
+/// Column computed by a `--with` expression
+#[derive(Debug, Clone)]
+struct Computed {
+    name: String,
+    expr: Expr,
+    /// Position of the column in the output, `None` appends it to the record
+    target: Option<usize>,
+}
+
+/// Renamed and computed columns of a command, see [Command::rename] and [Command::with]
+#[derive(Debug, Clone)]
+pub struct Derivation {
+    renames: Vec<(String, String)>,
+    columns: Vec<Computed>,
+}
+
+impl Derivation {
+    /// Parses the `old=new` renames and the `name=expression` computed columns, `None` when both are empty
+    /// # Errors
+    /// Returns an [Error::InvalidArgument] error if an option is not of the form `name=value`, and an
+    /// [Error::Expression] error if an expression is invalid.
+    pub fn parse(rename: &[String], with: &[String]) -> Result<Option<Derivation>, Error> {
+        if rename.is_empty() && with.is_empty() {
+            return Ok(None);
+        }
+        let split = |option: &str, source: &str| match source.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() && !value.trim().is_empty() => {
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(Error::InvalidArgument(format!("Expected name=value for --{}, found `{}`", option, source))),
+        };
+        let renames = rename.iter().map(|source| split("rename", source)).collect::<Result<Vec<_>, Error>>()?;
+        let columns = with
+            .iter()
+            .map(|source| {
+                let (name, source) = split("with", source)?;
+                Ok(Computed { name, expr: expression::parse(&source)?, target: None })
+            })
+            .collect::<Result<Vec<Computed>, Error>>()?;
+        Ok(Some(Derivation { renames, columns }))
+    }
+
+    /// Resolves the expressions against the `header` and returns the output header, `None` without header
+    /// # Errors
+    /// Returns an [Error::MissingColumn] error if a renamed or referenced column is not in the header, and an
+    /// [Error::InvalidArgument] error if columns are renamed without header.
+    pub fn resolve(&mut self, header: Option<&[String]>) -> Result<Option<Vec<String>>, Error> {
+        let Some(header) = header else {
+            if !self.renames.is_empty() {
+                return Err(Error::InvalidArgument("Renaming columns requires a header".to_string()));
+            }
+            for column in &mut self.columns {
+                column.expr = std::mem::replace(&mut column.expr, Expr::Literal(Value::Null)).resolve(None)?;
+            }
+            return Ok(None);
+        };
+        let mut names = header.to_vec();
+        for (old, new) in &self.renames {
+            match names.iter().position(|name| name.trim() == old) {
+                Some(position) => names[position] = new.clone(),
+                None => return Err(Error::MissingColumn(old.clone())),
+            }
+        }
+        for column in &mut self.columns {
+            column.expr = std::mem::replace(&mut column.expr, Expr::Literal(Value::Null)).resolve(Some(&names))?;
+            let target = names.iter().position(|name| name.trim() == column.name).unwrap_or_else(|| {
+                names.push(column.name.clone());
+                names.len() - 1
+            });
+            column.target = Some(target);
+        }
+        Ok(Some(names))
+    }
+
+    /// Returns the record with its computed columns, `row` being its 1-based number in the input
+    /// # Errors
+    /// Same as [Expr::evaluate].
+    pub fn apply<'a>(&self, fields: &[Cow<'a, str>], schema: &Schema, row: usize) -> Result<Vec<Cow<'a, str>>, Error> {
+        let mut record = fields.to_vec();
+        // Computed columns are strings, the input types only apply to the columns left as read
+        let mut schema = Cow::Borrowed(schema);
+        for column in &self.columns {
+            let value = Cow::Owned(column.expr.evaluate(&record, &schema, row)?.to_string());
+            let target = match column.target {
+                Some(target) => {
+                    // Short records are padded up to the computed column
+                    if record.len() <= target {
+                        record.resize(target + 1, Cow::Borrowed(""));
+                    }
+                    record[target] = value;
+                    target
+                }
+                None => {
+                    record.push(value);
+                    record.len() - 1
+                }
+            };
+            if schema.column_type(target) != ColumnType::String {
+                schema.to_mut().columns[target] = ColumnType::String;
+            }
+        }
+        Ok(record)
+    }
+}
+
+/// Output columns of a filter: the [Derivation] then the [Projection] of the command
+pub(crate) struct OutputColumns {
+    derivation: Option<Derivation>,
+    projection: Option<Projection>,
+    /// Resolved projection, `None` writes every column
+    columns: Option<Vec<usize>>,
+    schema: Schema,
+}
+
+impl OutputColumns {
+    /// Parses the output options of the command, resolving the expressions right away for files without header
+    pub(crate) fn new(filter_command: &Command, csv_struct: &CsvMetadata) -> Result<OutputColumns, Error> {
+        let mut derivation = Derivation::parse(&filter_command.rename, &filter_command.with)?;
+        let schema = match &mut derivation {
+            Some(derivation) => {
+                if !csv_struct.has_header {
+                    derivation.resolve(None)?;
+                }
+                Schema::from_column_types(&csv_struct.column_types)?
+            }
+            None => Schema::default(),
+        };
+        let projection = Projection::parse(&filter_command.select, &filter_command.drop)?;
+        Ok(OutputColumns { derivation, projection, columns: None, schema })
+    }
+
+    /// Resolves the output columns against the header, writing the output header when `write` is true
+    pub(crate) fn write_header<W: Write, S: AsRef<str>>(&mut self, writer: &mut CsvWriter<W>, header: &[S], write: bool) -> Result<(), Error> {
+        let mut header: Vec<String> = header.iter().map(|field| field.as_ref().to_string()).collect();
+        if let Some(derivation) = &mut self.derivation {
+            header = derivation.resolve(Some(&header))?.unwrap_or_default();
+        }
+        self.columns = self.projection.as_ref().map(|p| p.resolve(Some(&header), header.len())).transpose()?;
+        match write {
+            true => projection::write_projected(writer, &header, self.columns.as_deref()),
+            false => Ok(()),
+        }
+    }
+
+    /// Writes the output columns of the record at the 1-based `row`
+    pub(crate) fn write_record<W: Write>(&mut self, writer: &mut CsvWriter<W>, fields: &[Cow<'_, str>], row: usize) -> Result<(), Error> {
+        let derived;
+        let record = match &self.derivation {
+            Some(derivation) => {
+                derived = derivation.apply(fields, &self.schema, row)?;
+                &derived[..]
+            }
+            None => fields,
+        };
+        // Without header the columns are resolved against the first written record
+        if let (None, Some(projection)) = (&self.columns, &self.projection) {
+            self.columns = Some(projection.resolve::<&str>(None, record.len())?);
+        }
+        projection::write_projected(writer, record, self.columns.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derive(rename: &[&str], with: &[&str], header: &[&str], record: &[&str]) -> Result<(Vec<String>, Vec<String>), Error> {
+        let strings = |values: &[&str]| values.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let mut derivation = Derivation::parse(&strings(rename), &strings(with))?.unwrap();
+        let header = derivation.resolve(Some(&strings(header)))?.unwrap();
+        let record: Vec<Cow<str>> = record.iter().map(|field| Cow::Borrowed(*field)).collect();
+        let record = derivation.apply(&record, &Schema::default(), 2)?;
+        Ok((header, record.into_iter().map(Cow::into_owned).collect()))
+    }
+
+    #[test]
+    fn test_derivation() {
+        let (header, record) = derive(&["b=c"], &["d=a+c", "e=d*2", "a=concat(a,'!')"], &["a", "b"], &["1", "2"]).unwrap();
+        assert_eq!(header, vec!["a", "c", "d", "e"]);
+        assert_eq!(record, vec!["1!", "2", "3", "6"]);
+        let (_, record) = derive(&[], &["c=coalesce(b, 'none')"], &["a", "b"], &["1"]).unwrap();
+        assert_eq!(record, vec!["1", "", "none"]);
+    }
+
+    #[test]
+    fn test_derivation_typed_columns() {
+        let schema = Schema::from_column_types(&["int".to_string(), "int".to_string()]).unwrap();
+        let with = ["a=concat(a,'!')".to_string(), "b=a".to_string(), "c=b".to_string()];
+        let mut derivation = Derivation::parse(&[], &with).unwrap().unwrap();
+        derivation.resolve(Some(&["a".to_string(), "b".to_string()])).unwrap();
+        let record = [Cow::Borrowed("1"), Cow::Borrowed("2")];
+        let record = derivation.apply(&record, &schema, 2).unwrap();
+        assert_eq!(record, vec!["1!", "1!", "1!"]);
+        let record = derivation.apply(&[Cow::Borrowed("x"), Cow::Borrowed("2")], &schema, 3);
+        assert!(matches!(record, Err(Error::TypeConversion(_))));
+    }
+
+    #[test]
+    fn test_derivation_errors() {
+        assert!(matches!(derive(&["x=y"], &[], &["a"], &["1"]), Err(Error::MissingColumn(name)) if name == "x"));
+        assert!(matches!(derive(&["a=b"], &["c=a"], &["a"], &["1"]), Err(Error::MissingColumn(name)) if name == "a"));
+        assert!(matches!(derive(&[], &["total"], &["a"], &["1"]), Err(Error::InvalidArgument(_))));
+        assert!(matches!(derive(&[], &["c=a +"], &["a"], &["1"]), Err(Error::Expression(_))));
+        let mut derivation = Derivation::parse(&["a=b".to_string()], &[]).unwrap().unwrap();
+        assert!(matches!(derivation.resolve(None), Err(Error::InvalidArgument(_))));
+    }
+}
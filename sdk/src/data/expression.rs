@@ -0,0 +1,825 @@
+//! Expression language used by the `--where` filter and the `--with` computed columns
+//!
+//! An expression is parsed once into an [Expr] tree, its column names are resolved against the header with
+//! [Expr::resolve] and it is then evaluated against every record with [Expr::is_match] or [Expr::evaluate].
+//!
+//! # Grammar
+//! ```text
+//! expr       := and ( "||" and )*
+//! and        := unary ( "&&" unary )*
+//! unary      := "!" unary | comparison
+//! comparison := sum ( op sum | "in" "[" literal ( "," literal )* "]" | "matches" /regex/ )?
+//! op         := "==" | "!=" | "<" | "<=" | ">" | ">=" | "contains" | "starts_with" | "ends_with"
+//! sum        := product ( ( "+" | "-" ) product )*
+//! product    := operand ( ( "*" | "/" | "%" ) operand )*
+//! operand    := column | $index | "string" | 'string' | number | true | false | null
+//!             | function "(" expr ( "," expr )* ")" | "(" expr ")"
+//! ```
+//! Columns are referenced by name, by a backticked name when it contains spaces (`` `unit price` ``) or by their
+//! 1-based position (`$2`). Fields are typed following [CsvMetadata::column_types](crate::reader::CsvMetadata::column_types);
+//! a string field compared to a number or a boolean is converted first, so `age >= 30` works on untyped files.
+//!
+//! Arithmetic converts string fields to numbers the same way. Two ints give an int, except for `/` which always
+//! divides as floats or decimals, an int and a decimal give a decimal and floats are used otherwise. A null operand or
+//! a division by zero gives null.
+//!
+//! # Functions
+//! | Function | Result |
+//! |----------|--------|
+//! | `is_null(x)` | true for empty and missing fields |
+//! | `concat(x, ...)` | the values joined as text, nulls being empty |
+//! | `substr(x, start, length?)` | the characters from the 1-based `start`, to the end without `length` |
+//! | `upper(x)`, `lower(x)` | the text in upper or lower case |
+//! | `coalesce(x, ...)` | the first value that is not null or empty |
+//! | `year(x)`, `month(x)`, `day(x)`, `hour(x)`, `minute(x)`, `second(x)` | a part of a date or datetime |
+//!
+//! # Example
+//! ```
+//! use sdk::data::{expression, schema::Schema};
+//! let header = vec!["age".to_string(), "state".to_string(), "email".to_string()];
+//! let expr = expression::parse(r#"age >= 30 && (state == "CA" || state == "NY") && !is_null(email)"#)
+//!     .and_then(|expr| expr.resolve(Some(&header)))
+//!     .unwrap();
+//! let record = vec!["42".to_string(), "NY".to_string(), "a@example.com".to_string()];
+//! assert!(expr.is_match(&record, &Schema::default(), 2).unwrap());
+//! ```
+use std::cmp::Ordering;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use regex::Regex;
+use rust_decimal::Decimal;
+use super::schema::{ColumnType, Schema, Value};
+use crate::Error;
+
+/// Comparison operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn test(&self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// Substring operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOp {
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+/// Arithmetic operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl ArithOp {
+    fn symbol(&self) -> char {
+        match self {
+            ArithOp::Add => '+',
+            ArithOp::Sub => '-',
+            ArithOp::Mul => '*',
+            ArithOp::Div => '/',
+            ArithOp::Rem => '%',
+        }
+    }
+
+    /// Integer arithmetic, `None` on overflow
+    fn int(&self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            ArithOp::Add => a.checked_add(b),
+            ArithOp::Sub => a.checked_sub(b),
+            ArithOp::Mul => a.checked_mul(b),
+            ArithOp::Div => None,
+            ArithOp::Rem => a.checked_rem(b),
+        }
+    }
+
+    /// Decimal arithmetic, `None` on overflow
+    fn decimal(&self, a: Decimal, b: Decimal) -> Option<Decimal> {
+        match self {
+            ArithOp::Add => a.checked_add(b),
+            ArithOp::Sub => a.checked_sub(b),
+            ArithOp::Mul => a.checked_mul(b),
+            ArithOp::Div => a.checked_div(b),
+            ArithOp::Rem => a.checked_rem(b),
+        }
+    }
+
+    fn float(&self, a: f64, b: f64) -> f64 {
+        match self {
+            ArithOp::Add => a + b,
+            ArithOp::Sub => a - b,
+            ArithOp::Mul => a * b,
+            ArithOp::Div => a / b,
+            ArithOp::Rem => a % b,
+        }
+    }
+}
+
+/// Built-in functions, see the [module](self) documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    IsNull,
+    Concat,
+    Substr,
+    Upper,
+    Lower,
+    Coalesce,
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "is_null" => Some(Function::IsNull),
+            "concat" => Some(Function::Concat),
+            "substr" => Some(Function::Substr),
+            "upper" => Some(Function::Upper),
+            "lower" => Some(Function::Lower),
+            "coalesce" => Some(Function::Coalesce),
+            "year" => Some(Function::Year),
+            "month" => Some(Function::Month),
+            "day" => Some(Function::Day),
+            "hour" => Some(Function::Hour),
+            "minute" => Some(Function::Minute),
+            "second" => Some(Function::Second),
+            _ => None,
+        }
+    }
+
+    /// Minimum and maximum number of arguments, `None` when unbounded
+    fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            Function::Concat | Function::Coalesce => (1, None),
+            Function::Substr => (2, Some(3)),
+            _ => (1, Some(1)),
+        }
+    }
+
+    /// Applies the function to its evaluated arguments
+    fn call(&self, mut args: Vec<Value>, row: usize) -> Result<Value, Error> {
+        let text = |value: &Value| value.to_string();
+        // Empty fields of string columns are nulls too
+        let is_null = |value: &Value| value.is_null() || *value == Value::String(String::new());
+        Ok(match self {
+            Function::IsNull => Value::Bool(is_null(&args[0])),
+            Function::Concat => Value::String(args.iter().map(text).collect()),
+            Function::Coalesce => args.into_iter().find(|value| !is_null(value)).unwrap_or(Value::Null),
+            _ if is_null(&args[0]) => Value::Null,
+            Function::Upper => Value::String(text(&args[0]).to_uppercase()),
+            Function::Lower => Value::String(text(&args[0]).to_lowercase()),
+            Function::Substr => {
+                let length = match args.len() {
+                    3 => Some(integer(args.pop().unwrap_or(Value::Null), "substr", row)?.max(0) as usize),
+                    _ => None,
+                };
+                let start = integer(args.pop().unwrap_or(Value::Null), "substr", row)?.max(1) as usize - 1;
+                let text = text(&args[0]);
+                let chars = text.chars().skip(start);
+                Value::String(match length {
+                    Some(length) => chars.take(length).collect(),
+                    None => chars.collect(),
+                })
+            }
+            Function::Year | Function::Month | Function::Day | Function::Hour | Function::Minute | Function::Second => {
+                let Some(datetime) = datetime(&args[0]) else {
+                    return Err(Error::Expression(format!("Expected a date at row {}, found `{}`", row, args[0])));
+                };
+                Value::Int(match self {
+                    Function::Year => datetime.year() as i64,
+                    Function::Month => datetime.month() as i64,
+                    Function::Day => datetime.day() as i64,
+                    Function::Hour => datetime.hour() as i64,
+                    Function::Minute => datetime.minute() as i64,
+                    _ => datetime.second() as i64,
+                })
+            }
+        })
+    }
+}
+
+/// Parsed expression tree
+#[derive(Debug, Clone)]
+pub enum Expr {
+    /// Column referenced by name, replaced by [Expr::Index] once resolved
+    Column(String),
+    /// Column referenced by its 0-based position
+    Index(usize),
+    Literal(Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    Text(Box<Expr>, TextOp, Box<Expr>),
+    In(Box<Expr>, Vec<Value>),
+    Matches(Box<Expr>, Regex),
+    Arith(Box<Expr>, ArithOp, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Index(usize),
+    Str(String),
+    Number(String),
+    Regex(String),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Compare(CompareOp),
+    Arith(ArithOp),
+}
+
+fn syntax_error(message: String, position: usize) -> Error {
+    Error::Expression(format!("{} at position {} of the expression", message, position))
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let start = pos;
+        let c = chars[pos];
+        let next = chars.get(pos + 1).copied();
+        // After an operand `-` and `/` are arithmetic operators, otherwise a negative number and a regex
+        let after_operand = match tokens.last() {
+            Some((Token::Ident(ident), _)) => !["contains", "starts_with", "ends_with", "in", "matches"].contains(&ident.as_str()),
+            Some((token, _)) => matches!(token, Token::Index(_) | Token::Str(_) | Token::Number(_) | Token::RParen | Token::RBracket),
+            None => false,
+        };
+        let token = match c {
+            c if c.is_whitespace() => {
+                pos += 1;
+                continue;
+            }
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ',' => Token::Comma,
+            '&' if next == Some('&') => Token::And,
+            '|' if next == Some('|') => Token::Or,
+            '=' if next == Some('=') => Token::Compare(CompareOp::Eq),
+            '!' if next == Some('=') => Token::Compare(CompareOp::Ne),
+            '<' if next == Some('=') => Token::Compare(CompareOp::Le),
+            '>' if next == Some('=') => Token::Compare(CompareOp::Ge),
+            '!' => Token::Not,
+            '<' => Token::Compare(CompareOp::Lt),
+            '>' => Token::Compare(CompareOp::Gt),
+            '+' => Token::Arith(ArithOp::Add),
+            '*' => Token::Arith(ArithOp::Mul),
+            '%' => Token::Arith(ArithOp::Rem),
+            '/' if after_operand => Token::Arith(ArithOp::Div),
+            '-' if after_operand || !next.is_some_and(|n| n.is_ascii_digit()) => Token::Arith(ArithOp::Sub),
+            '"' | '\'' | '`' | '/' => {
+                let regex = c == '/';
+                let mut text = String::new();
+                pos += 1;
+                loop {
+                    match chars.get(pos) {
+                        None => return Err(syntax_error(format!("Unterminated `{}`", c), start)),
+                        Some(&end) if end == c => break,
+                        // Regex escapes are kept for the regex engine, only the closing slash is unescaped
+                        Some('\\') if regex => {
+                            match chars.get(pos + 1) {
+                                Some('/') => text.push('/'),
+                                Some(&escaped) => {
+                                    text.push('\\');
+                                    text.push(escaped);
+                                }
+                                None => text.push('\\'),
+                            }
+                            pos += 1;
+                        }
+                        Some('\\') => {
+                            match chars.get(pos + 1) {
+                                Some('n') => text.push('\n'),
+                                Some('t') => text.push('\t'),
+                                Some(&escaped) => text.push(escaped),
+                                None => text.push('\\'),
+                            }
+                            pos += 1;
+                        }
+                        Some(&other) => text.push(other),
+                    }
+                    pos += 1;
+                }
+                match c {
+                    '`' => Token::Ident(text),
+                    '/' => Token::Regex(text),
+                    _ => Token::Str(text),
+                }
+            }
+            '$' => {
+                let digits: String = chars[pos + 1..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                match digits.parse::<usize>() {
+                    Ok(index) if index > 0 => {
+                        pos += digits.len();
+                        Token::Index(index - 1)
+                    }
+                    _ => return Err(syntax_error("Expected a 1-based column position after `$`".to_string(), start)),
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut number = String::from(c);
+                while let Some(&d) = chars.get(pos + 1) {
+                    if !(d.is_ascii_digit() || d == '.') {
+                        break;
+                    }
+                    number.push(d);
+                    pos += 1;
+                }
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::from(c);
+                while let Some(&d) = chars.get(pos + 1) {
+                    if !(d.is_alphanumeric() || d == '_' || d == '.') {
+                        break;
+                    }
+                    ident.push(d);
+                    pos += 1;
+                }
+                Token::Ident(ident)
+            }
+            other => return Err(syntax_error(format!("Unexpected `{}`", other), start)),
+        };
+        if matches!(token, Token::And | Token::Or) || matches!(token, Token::Compare(_)) && (next == Some('=')) {
+            pos += 1;
+        }
+        pos += 1;
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|(_, position)| *position).unwrap_or(self.end)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), Error> {
+        let position = self.position();
+        match self.advance() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(syntax_error(format!("Expected {:?}, found {:?}", expected, token), position)),
+            None => Err(syntax_error(format!("Expected {:?}", expected), position)),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, Error> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, Error> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            left = Expr::And(Box::new(left), Box::new(self.parse_unary()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, Error> {
+        let left = self.parse_sum()?;
+        let op = match self.peek() {
+            Some(Token::Compare(op)) => *op,
+            Some(Token::Ident(keyword)) => match keyword.as_str() {
+                "contains" => return self.parse_text(left, TextOp::Contains),
+                "starts_with" => return self.parse_text(left, TextOp::StartsWith),
+                "ends_with" => return self.parse_text(left, TextOp::EndsWith),
+                "in" => {
+                    self.advance();
+                    return Ok(Expr::In(Box::new(left), self.parse_list()?));
+                }
+                "matches" => {
+                    self.advance();
+                    let position = self.position();
+                    let pattern = match self.advance() {
+                        Some(Token::Regex(pattern)) | Some(Token::Str(pattern)) => pattern,
+                        _ => return Err(syntax_error("Expected a /regex/ after `matches`".to_string(), position)),
+                    };
+                    let regex = Regex::new(&pattern)
+                        .map_err(|e| syntax_error(format!("Invalid regex: {}", e), position))?;
+                    return Ok(Expr::Matches(Box::new(left), regex));
+                }
+                _ => return Ok(left),
+            },
+            _ => return Ok(left),
+        };
+        self.advance();
+        let right = self.parse_sum()?;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(right)))
+    }
+
+    fn parse_text(&mut self, left: Expr, op: TextOp) -> Result<Expr, Error> {
+        self.advance();
+        let right = self.parse_sum()?;
+        Ok(Expr::Text(Box::new(left), op, Box::new(right)))
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, Error> {
+        let mut left = self.parse_product()?;
+        while let Some(Token::Arith(op @ (ArithOp::Add | ArithOp::Sub))) = self.peek() {
+            let op = *op;
+            self.advance();
+            left = Expr::Arith(Box::new(left), op, Box::new(self.parse_product()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, Error> {
+        let mut left = self.parse_operand()?;
+        while let Some(Token::Arith(op @ (ArithOp::Mul | ArithOp::Div | ArithOp::Rem))) = self.peek() {
+            let op = *op;
+            self.advance();
+            left = Expr::Arith(Box::new(left), op, Box::new(self.parse_operand()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<Value>, Error> {
+        self.expect(Token::LBracket)?;
+        let mut values = Vec::new();
+        loop {
+            let position = self.position();
+            match self.parse_operand()? {
+                Expr::Literal(value) => values.push(value),
+                _ => return Err(syntax_error("Expected a literal in the list".to_string(), position)),
+            }
+            let position = self.position();
+            match self.advance() {
+                Some(Token::Comma) => continue,
+                Some(Token::RBracket) => return Ok(values),
+                _ => return Err(syntax_error("Expected `,` or `]`".to_string(), position)),
+            }
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, Error> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Str(text)) => Ok(Expr::Literal(Value::String(text))),
+            Some(Token::Number(number)) => {
+                let value = ColumnType::Int.parse(&number).or_else(|| ColumnType::Float.parse(&number));
+                match value {
+                    Some(value) => Ok(Expr::Literal(value)),
+                    None => Err(syntax_error(format!("Invalid number `{}`", number), position)),
+                }
+            }
+            Some(Token::Index(index)) => Ok(Expr::Index(index)),
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.peek() == Some(&Token::LParen) => self.parse_call(&ident, position),
+                _ => Ok(Expr::Column(ident)),
+            },
+            Some(token) => Err(syntax_error(format!("Unexpected {:?}", token), position)),
+            None => Err(syntax_error("Unexpected end".to_string(), position)),
+        }
+    }
+
+    fn parse_call(&mut self, name: &str, position: usize) -> Result<Expr, Error> {
+        let function = Function::from_name(name)
+            .ok_or_else(|| syntax_error(format!("Unknown function `{}`", name), position))?;
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::RParen) {
+            args.push(self.parse_or()?);
+            while self.peek() == Some(&Token::Comma) {
+                self.advance();
+                args.push(self.parse_or()?);
+            }
+        }
+        self.expect(Token::RParen)?;
+        let expected = match function.arity() {
+            (min, _) if args.len() < min => Some(format!("at least {}", min)),
+            (_, Some(max)) if args.len() > max => Some(format!("at most {}", max)),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            return Err(syntax_error(
+                format!("Function `{}` expects {} argument(s), found {}", name, expected, args.len()),
+                position,
+            ));
+        }
+        Ok(Expr::Call(function, args))
+    }
+}
+
+/// Parses an expression
+/// # Errors
+/// Returns an [Error::Expression] error with the position of the offending token if the expression is invalid.
+pub fn parse(source: &str) -> Result<Expr, Error> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, pos: 0, end: source.chars().count() };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(syntax_error(format!("Unexpected {:?}", token), parser.position())),
+    }
+}
+
+/// Converts a string operand to the type of the other operand so untyped columns compare with typed literals
+fn coerce(value: Value, target: &Value) -> Value {
+    let Value::String(text) = &value else { return value };
+    let converted = match target {
+        Value::Int(_) | Value::Float(_) | Value::Decimal(_) => {
+            ColumnType::Int.parse(text).or_else(|| ColumnType::Float.parse(text))
+        }
+        Value::Bool(_) | Value::Date(_) | Value::Datetime(_) => target.column_type().and_then(|t| t.parse(text)),
+        _ => None,
+    };
+    converted.unwrap_or(value)
+}
+
+fn compare(left: Value, right: Value) -> Option<Ordering> {
+    let left = coerce(left, &right);
+    let right = coerce(right, &left);
+    left.compare(&right)
+}
+
+/// Applies an arithmetic operator, see the [module](self) documentation for the result types
+fn arithmetic(left: Value, op: ArithOp, right: Value, row: usize) -> Result<Value, Error> {
+    let number = Value::Int(0);
+    let (left, right) = (coerce(left, &number), coerce(right, &number));
+    if left.is_null() || right.is_null() || (matches!(op, ArithOp::Div | ArithOp::Rem) && right.as_f64() == Some(0.0)) {
+        return Ok(Value::Null);
+    }
+    let result = match (&left, &right) {
+        (Value::Int(a), Value::Int(b)) if op != ArithOp::Div => op.int(*a, *b).map(Value::Int),
+        (Value::Decimal(_), Value::Int(_) | Value::Decimal(_)) | (Value::Int(_), Value::Decimal(_)) => {
+            op.decimal(decimal(&left), decimal(&right)).map(Value::Decimal)
+        }
+        _ => match (left.as_f64(), right.as_f64()) {
+            (Some(a), Some(b)) => Some(Value::Float(op.float(a, b))),
+            _ => None,
+        },
+    };
+    result.ok_or_else(|| Error::Expression(format!("Cannot compute `{}` {} `{}` at row {}", left, op.symbol(), right, row)))
+}
+
+fn decimal(value: &Value) -> Decimal {
+    match value {
+        Value::Int(v) => Decimal::from(*v),
+        Value::Decimal(v) => *v,
+        _ => Decimal::ZERO,
+    }
+}
+
+/// Integer argument of a function, strings being converted
+fn integer(value: Value, function: &str, row: usize) -> Result<i64, Error> {
+    match coerce(value, &Value::Int(0)) {
+        Value::Int(v) => Ok(v),
+        other => Err(Error::Expression(format!("`{}` expects an integer at row {}, found `{}`", function, row, other))),
+    }
+}
+
+/// Date or datetime view of a value, strings being parsed as datetimes then dates
+fn datetime(value: &Value) -> Option<NaiveDateTime> {
+    match value {
+        Value::Date(date) => date.and_hms_opt(0, 0, 0),
+        Value::Datetime(datetime) => Some(*datetime),
+        Value::String(text) => ColumnType::Datetime.parse(text).or_else(|| ColumnType::Date.parse(text)).as_ref().and_then(datetime),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> Result<bool, Error> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::Null => Ok(false),
+        other => Err(Error::Expression(format!("Expression does not evaluate to a boolean: `{}`", other))),
+    }
+}
+
+impl Expr {
+    /// Replaces column names by their position in `header`
+    /// # Errors
+    /// Returns an [Error::MissingColumn] error if a column is not in the header, or if a column is referenced by
+    /// name while the file has no header.
+    pub fn resolve(self, header: Option<&[String]>) -> Result<Expr, Error> {
+        let resolve = |expr: Box<Expr>| expr.resolve(header).map(Box::new);
+        Ok(match self {
+            Expr::Column(name) => {
+                let position = header.and_then(|header| header.iter().position(|field| field.trim() == name));
+                match position {
+                    Some(index) => Expr::Index(index),
+                    None => return Err(Error::MissingColumn(name)),
+                }
+            }
+            Expr::Not(expr) => Expr::Not(resolve(expr)?),
+            Expr::And(left, right) => Expr::And(resolve(left)?, resolve(right)?),
+            Expr::Or(left, right) => Expr::Or(resolve(left)?, resolve(right)?),
+            Expr::Compare(left, op, right) => Expr::Compare(resolve(left)?, op, resolve(right)?),
+            Expr::Text(left, op, right) => Expr::Text(resolve(left)?, op, resolve(right)?),
+            Expr::In(expr, values) => Expr::In(resolve(expr)?, values),
+            Expr::Matches(expr, regex) => Expr::Matches(resolve(expr)?, regex),
+            Expr::Arith(left, op, right) => Expr::Arith(resolve(left)?, op, resolve(right)?),
+            Expr::Call(function, args) => Expr::Call(
+                function,
+                args.into_iter().map(|arg| arg.resolve(header)).collect::<Result<Vec<Expr>, Error>>()?,
+            ),
+            resolved => resolved,
+        })
+    }
+
+    /// Evaluates the expression against the fields of the record located at `row`
+    /// # Errors
+    /// Returns an error if a field cannot be converted to its column type or if a column is still unresolved.
+    pub fn evaluate<S: AsRef<str>>(&self, fields: &[S], schema: &Schema, row: usize) -> Result<Value, Error> {
+        let eval = |expr: &Expr| expr.evaluate(fields, schema, row);
+        Ok(match self {
+            Expr::Column(name) => return Err(Error::Expression(format!("Column {} was not resolved against the header", name))),
+            Expr::Index(index) => match fields.get(*index) {
+                Some(field) => schema.parse_field(row, *index, field.as_ref())?,
+                None => Value::Null,
+            },
+            Expr::Literal(value) => value.clone(),
+            Expr::Not(expr) => Value::Bool(!truthy(&eval(expr)?)?),
+            Expr::And(left, right) => Value::Bool(truthy(&eval(left)?)? && truthy(&eval(right)?)?),
+            Expr::Or(left, right) => Value::Bool(truthy(&eval(left)?)? || truthy(&eval(right)?)?),
+            Expr::Compare(left, op, right) => {
+                let (left, right) = (eval(left)?, eval(right)?);
+                if left.is_null() || right.is_null() {
+                    Value::Bool(false)
+                } else {
+                    Value::Bool(compare(left, right).is_some_and(|ordering| op.test(ordering)))
+                }
+            }
+            Expr::Text(left, op, right) => {
+                let (left, right) = (eval(left)?, eval(right)?);
+                if left.is_null() || right.is_null() {
+                    Value::Bool(false)
+                } else {
+                    let (left, right) = (left.to_string(), right.to_string());
+                    Value::Bool(match op {
+                        TextOp::Contains => left.contains(&right),
+                        TextOp::StartsWith => left.starts_with(&right),
+                        TextOp::EndsWith => left.ends_with(&right),
+                    })
+                }
+            }
+            Expr::In(expr, values) => {
+                let value = eval(expr)?;
+                Value::Bool(!value.is_null() && values.iter().any(|v| compare(value.clone(), v.clone()) == Some(Ordering::Equal)))
+            }
+            Expr::Matches(expr, regex) => {
+                let value = eval(expr)?;
+                Value::Bool(!value.is_null() && regex.is_match(&value.to_string()))
+            }
+            Expr::Arith(left, op, right) => arithmetic(eval(left)?, *op, eval(right)?, row)?,
+            Expr::Call(function, args) => function.call(args.iter().map(eval).collect::<Result<Vec<Value>, Error>>()?, row)?,
+        })
+    }
+
+    /// Evaluates the expression as a predicate, nulls are treated as `false`
+    pub fn is_match<S: AsRef<str>>(&self, fields: &[S], schema: &Schema, row: usize) -> Result<bool, Error> {
+        truthy(&self.evaluate(fields, schema, row)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, record: &[&str]) -> bool {
+        let header: Vec<String> = ["age", "state", "email", "sku"].iter().map(|s| s.to_string()).collect();
+        let record: Vec<String> = record.iter().map(|s| s.to_string()).collect();
+        let expr = parse(source).and_then(|expr| expr.resolve(Some(&header))).unwrap();
+        expr.is_match(&record, &Schema::default(), 2).unwrap()
+    }
+
+    #[test]
+    fn test_boolean_logic() {
+        let source = r#"age >= 30 && (state == "CA" || state == "NY") && !is_null(email)"#;
+        assert!(eval(source, &["30", "CA", "a@b.c", ""]));
+        assert!(!eval(source, &["29", "CA", "a@b.c", ""]));
+        assert!(!eval(source, &["45", "TX", "a@b.c", ""]));
+        assert!(!eval(source, &["45", "NY", "", ""]));
+    }
+
+    #[test]
+    fn test_operators() {
+        let record = ["7", "CA", "jane@example.com", "AB-1234"];
+        assert!(eval("state in ['CA', 'NY']", &record));
+        assert!(eval("age != 8 && age < 10 && age <= 7 && age > 6.5", &record));
+        assert!(eval("email contains 'example' && email starts_with 'jane' && email ends_with '.com'", &record));
+        assert!(eval(r"sku matches /^AB-\d{4}$/", &record));
+        assert!(eval("$2 == 'CA'", &record));
+        assert!(eval("is_null($9)", &record));
+    }
+
+    fn value(source: &str, record: &[&str]) -> Result<Value, Error> {
+        let header: Vec<String> = ["price", "qty", "name", "at"].iter().map(|s| s.to_string()).collect();
+        let schema = Schema::from_column_types(&["decimal".to_string()])?;
+        parse(source)?.resolve(Some(&header))?.evaluate(record, &schema, 2)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let record = ["2.50", "4", "Ada Lovelace", "2024-03-09 17:45:30"];
+        assert_eq!(value("price * qty", &record).unwrap().to_string(), "10.00");
+        assert_eq!(value("qty * 3 - 2 % 3", &record).unwrap(), Value::Int(10));
+        assert_eq!(value("(qty-1) / 2", &record).unwrap(), Value::Float(1.5));
+        assert_eq!(value("qty + -1.5", &record).unwrap(), Value::Float(2.5));
+        assert!(value("qty / 0", &record).unwrap().is_null());
+        assert!(value("qty + $9", &record).unwrap().is_null());
+        assert!(value("price * qty > 9.99 && $2 - 1 == 3", &record).unwrap() == Value::Bool(true));
+        assert!(matches!(value("name * 2", &record), Err(Error::Expression(message)) if message.contains("row 2")));
+    }
+
+    #[test]
+    fn test_functions() {
+        let record = ["", "4", "Ada Lovelace", "2024-03-09 17:45:30"];
+        let text = |source: &str| value(source, &record).unwrap().to_string();
+        assert_eq!(text("concat(upper(substr(name, 1, 3)), '-', lower(substr(name, 5)), qty)"), "ADA-lovelace4");
+        assert_eq!(text("substr(name, 0, 2)"), "Ad");
+        assert_eq!(text("coalesce(price, $9, qty)"), "4");
+        assert_eq!(text("concat(year(at), '/', month(at), '/', day(at), ' ', hour(at), minute(at), second(at))"), "2024/3/9 174530");
+        assert_eq!(text("day('2024-02-29')"), "29");
+        assert!(value("upper(price)", &record).unwrap().is_null());
+        assert!(value("year(name)", &record).is_err());
+        assert!(parse("substr(name)").is_err());
+        assert!(parse("concat()").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse("age >= ").is_err());
+        assert!(parse("age == 'open").is_err());
+        assert!(parse("sku matches /[/").is_err());
+        assert!(parse("unknown(age)").is_err());
+        let header = vec!["age".to_string()];
+        assert!(parse("height > 1").unwrap().resolve(Some(&header)).is_err());
+        assert!(parse("age > 1").unwrap().resolve(None).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Schema inference for undocumented csv files
+//!
+//! [infer_schema] samples the records of a [FileRead] and narrows down, for every column, the most specific
+//! [ColumnType] able to parse all of its non-empty fields. The resulting [InferredSchema] can be printed or turned
+//! back into `--column-types` with [InferredSchema::column_types].
+use std::fmt;
+use clap::Parser;
+use super::manipulation::FileRead;
+use super::schema::{ColumnType, Schema, Value};
+use super::super::reader::CsvMetadata;
+use crate::Error;
+
+/// Candidate types ordered from the most to the least specific, [ColumnType::String] always matches
+const CANDIDATES: [ColumnType; 6] = [
+    ColumnType::Int,
+    ColumnType::Float,
+    ColumnType::Bool,
+    ColumnType::Date,
+    ColumnType::Datetime,
+    ColumnType::String,
+];
+
+/// Command struct to hold the schema inference arguments
+#[derive(Parser, Debug)]
+pub struct SchemaCommand {
+    #[clap(long, help = "Number of records to sample, defaults to the whole file")]
+    pub sample: Option<usize>,
+    #[clap(flatten)]
+    pub csv: CsvMetadata,
+}
+
+/// Running min/max of a column for a candidate type
+#[derive(Debug, Clone)]
+struct Candidate {
+    column_type: ColumnType,
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+impl Candidate {
+    fn update(&mut self, value: Value) {
+        if self.min.as_ref().is_none_or(|min| value.compare(min) == Some(std::cmp::Ordering::Less)) {
+            self.min = Some(value.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| value.compare(max) == Some(std::cmp::Ordering::Greater)) {
+            self.max = Some(value);
+        }
+    }
+}
+
+/// Inferred description of a single column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSummary {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+}
+
+/// Inferred description of a csv file
+#[derive(Debug, Clone, PartialEq)]
+pub struct InferredSchema {
+    pub columns: Vec<ColumnSummary>,
+    /// Number of records sampled, header excluded
+    pub rows: usize,
+}
+
+impl InferredSchema {
+    /// Returns the inferred types as a [Schema]
+    pub fn to_schema(&self) -> Schema {
+        Schema { columns: self.columns.iter().map(|column| column.column_type).collect() }
+    }
+
+    /// Returns the inferred types in the format of [CsvMetadata::column_types]
+    pub fn column_types(&self) -> Vec<String> {
+        self.columns.iter().map(|column| column.column_type.to_string()).collect()
+    }
+}
+
+impl fmt::Display for InferredSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let display = |value: &Option<Value>| value.as_ref().map(|v| v.to_string()).unwrap_or_default();
+        let name_width = self.columns.iter().map(|c| c.name.len()).chain([6]).max().unwrap_or(6);
+        writeln!(f, "{:<name_width$}  {:<8}  {:<8}  {:<20}  max", "column", "type", "nullable", "min")?;
+        for column in &self.columns {
+            writeln!(
+                f,
+                "{:<name_width$}  {:<8}  {:<8}  {:<20}  {}",
+                column.name,
+                column.column_type,
+                column.nullable,
+                display(&column.min),
+                display(&column.max),
+            )?;
+        }
+        writeln!(f, "rows sampled: {}", self.rows)?;
+        write!(f, "--column-types {}", self.column_types().join(","))
+    }
+}
+
+struct ColumnState {
+    name: String,
+    nullable: bool,
+    candidates: Vec<Candidate>,
+}
+
+impl ColumnState {
+    fn new(name: String) -> ColumnState {
+        let candidates = CANDIDATES
+            .iter()
+            .map(|column_type| Candidate { column_type: *column_type, min: None, max: None })
+            .collect();
+        ColumnState { name, nullable: false, candidates }
+    }
+
+    fn observe(&mut self, field: Option<&str>) {
+        let field = field.map(str::trim).unwrap_or_default();
+        if field.is_empty() {
+            self.nullable = true;
+            return;
+        }
+        self.candidates.retain_mut(|candidate| match candidate.column_type.parse(field) {
+            Some(value) => {
+                candidate.update(value);
+                true
+            }
+            None => false,
+        });
+    }
+
+    fn summary(self) -> ColumnSummary {
+        // String never fails to parse so there is always a candidate left
+        let best = self.candidates.into_iter().next().expect("string candidate");
+        ColumnSummary {
+            name: self.name,
+            column_type: best.column_type,
+            nullable: self.nullable,
+            min: best.min,
+            max: best.max,
+        }
+    }
+}
+
+/// Infers the schema of a csv file from its first `sample` records, or the whole file when `sample` is `None`
+///
+/// Columns are named after the header, or `column_<n>` (1-based) when [CsvMetadata::has_header] is false.
+/// A column is nullable when at least one of its sampled fields is empty or missing.
+/// # Example
+/// ```
+/// # use sdk::data::{inference, manipulation::FileRead, schema::ColumnType};
+/// # use sdk::reader::CsvMetadata;
+/// let lines = vec![Ok("id,score".to_string()), Ok("1,0.5".to_string()), Ok("2,".to_string())];
+/// let csv_struct = CsvMetadata {
+///     file: "scores.csv".to_string(),
+///     ..CsvMetadata::default()
+/// };
+/// let schema = inference::infer_schema(FileRead::Iterator(Box::new(lines.into_iter())), &csv_struct, None).unwrap();
+/// assert_eq!(schema.columns[0].column_type, ColumnType::Int);
+/// assert!(schema.columns[1].nullable);
+/// ```
+pub fn infer_schema(buffer: FileRead, csv_struct: &CsvMetadata, sample: Option<usize>) -> Result<InferredSchema, Error> {
+    let mut columns: Vec<ColumnState> = Vec::new();
+    let mut rows = 0;
+    let mut records = buffer.records(csv_struct.delimiter);
+
+    if csv_struct.has_header {
+        if let Some(header) = records.next() {
+            columns = header?.iter().map(|name| ColumnState::new(name.trim().to_string())).collect();
+        }
+    }
+
+    for record in records.take(sample.unwrap_or(usize::MAX)) {
+        let fields = record?;
+        while columns.len() < fields.len() {
+            let name = format!("column_{}", columns.len() + 1);
+            // Columns discovered late were missing from the previous records
+            let mut state = ColumnState::new(name);
+            state.nullable = rows > 0;
+            columns.push(state);
+        }
+        for (index, column) in columns.iter_mut().enumerate() {
+            column.observe(fields.get(index).map(|field| field.as_str()));
+        }
+        rows += 1;
+    }
+
+    Ok(InferredSchema { columns: columns.into_iter().map(ColumnState::summary).collect(), rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn reader(data: &str) -> FileRead {
+        let lines: Vec<Result<String, io::Error>> = data.lines().map(|line| Ok(line.to_string())).collect();
+        FileRead::Iterator(Box::new(lines.into_iter()))
+    }
+
+    fn metadata(has_header: bool) -> CsvMetadata {
+        CsvMetadata {
+            file: "in-memory.csv".to_string(),
+            has_header,
+            ..CsvMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_infer_types() {
+        let data = "id,price,active,day,note\n1,2.5,yes,2024-01-31,a\n3,10,no,2023-12-01,\n2,-1,yes,2024-02-01,b";
+        let schema = infer_schema(reader(data), &metadata(true), None).unwrap();
+        assert_eq!(schema.rows, 3);
+        assert_eq!(schema.column_types(), vec!["int", "float", "bool", "date", "string"]);
+        assert_eq!(schema.columns[0].min, Some(Value::Int(1)));
+        assert_eq!(schema.columns[1].max, Some(Value::Float(10.0)));
+        assert!(!schema.columns[0].nullable);
+        assert!(schema.columns[4].nullable);
+    }
+
+    #[test]
+    fn test_infer_sample_without_header() {
+        let schema = infer_schema(reader("1,x\n2,y\nabc,z"), &metadata(false), Some(2)).unwrap();
+        assert_eq!(schema.rows, 2);
+        assert_eq!(schema.columns[0].name, "column_1");
+        assert_eq!(schema.columns[0].column_type, ColumnType::Int);
+    }
+}
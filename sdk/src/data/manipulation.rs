@@ -1,21 +1,53 @@
 //! Data Manipulation modules and functionalities
 
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read, Write};
 use super::super::writer;
 use super::super::reader::CsvMetadata;
+use super::super::compression::Compression;
+use super::tokenizer;
+use super::schema::{ColumnType, ConversionError, Schema};
+use super::expression;
+use super::mapped::MappedFile;
+use super::computed::OutputColumns;
+use super::matching::{MatchMode, QueryMatcher, QueryMode};
+use crate::Error;
 use clap::Parser;
 
 /// Command struct to hold the query, column name and output path
+///
+/// The [Default] command has the defaults of the command line options and filters an empty [CsvMetadata::file].
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Default)]
 pub struct Command {
     #[clap(long, value_delimiter = ',', help = "Query to filter the data comma separated")]
     pub query: Vec<String>,
     #[clap(long, value_delimiter = ',', help = "Column name to filter the data comma separated")]
     pub column: Vec<String>,
-    #[clap(long, help = "Output path for the filtered data")]
+    #[clap(long, value_enum, default_value_t = MatchMode::Exact, help = "How queries are compared to the fields")]
+    pub match_mode: MatchMode,
+    #[clap(long, value_enum, default_value_t = QueryMode::All, help = "Whether all or any of the queries must match, used by anyfilter")]
+    pub query_mode: QueryMode,
+    #[clap(long = "where", help = "Filter expression, e.g. `age >= 30 && (state == \"CA\" || state == \"NY\")`")]
+    pub where_clause: Option<String>,
+    #[clap(long, help = "Output path for the filtered data, defaults to stdout")]
     pub output_path: Option<String>,
+    #[clap(long, value_parser = crate::reader::parse_delimiter, help = "Delimiter of the output data, defaults to the input delimiter")]
+    pub output_delimiter: Option<char>,
+    #[clap(long, value_enum, help = "Compression of the output data, defaults to the output path extension, e.g. `*.csv.gz`")]
+    pub compress: Option<Compression>,
+    #[clap(long, help = "Filter concurrently on this many threads, the files of a glob pattern or byte ranges of a single file, 0 uses one thread per core, not supported when reading stdin")]
+    pub threads: Option<usize>,
+    #[clap(long, action, help = "Keep the records of concurrently filtered files in the order of the files")]
+    pub preserve_order: bool,
+    #[clap(long, value_delimiter = ',', help = "Columns to write in this order comma separated: names, positions `$2`, ranges `$5-$7` or regexes `/^addr_/`")]
+    pub select: Vec<String>,
+    #[clap(long, value_delimiter = ',', help = "Columns to leave out of the output comma separated, same syntax as --select")]
+    pub drop: Vec<String>,
+    #[clap(long, value_delimiter = ',', help = "Rename columns comma separated, e.g. `qty=quantity`")]
+    pub rename: Vec<String>,
+    #[clap(long, help = "Add a column computed by an expression, e.g. `total=price*qty`, or replace a column of the same name. Repeat for several columns")]
+    pub with: Vec<String>,
     #[clap(subcommand)]
     pub subcommand: Subcommand
 }
@@ -30,145 +62,308 @@ pub enum Subcommand {
     Filter(CsvMetadata)
 }
 
-/// An iterator variant that yields strings or IO errors.
+impl Default for Subcommand {
+    fn default() -> Subcommand {
+        Subcommand::Filter(CsvMetadata::default())
+    }
+}
+
+/// An iterator variant that yields strings or IO errors, a buffered reader over any [Read] source, or a memory
+/// mapped file.
 /// 
 /// # Type Parameters
 /// 
 /// * `Item` - Each iteration produces a `Result<String, std::io::Error>`
 ///
-/// The iterator is boxed to allow for dynamic dispatch.
-///
+/// The iterator and the reader are boxed to allow for dynamic dispatch, so files, stdin, in-memory buffers,
+/// sockets or decompressors are all read the same way. The filters read the records of a [MappedFile] without
+/// copying them, see [mapped](super::mapped).
+/// # Example
+/// ```
+/// # use sdk::data::manipulation::FileRead;
+/// let buffer = FileRead::from_reader(std::io::Cursor::new(b"key,val\n1,a\n".to_vec()));
+/// assert_eq!(buffer.lines().count(), 2);
+/// ```
 pub enum FileRead {
     Iterator(Box<dyn Iterator<Item = Result<String, std::io::Error>>>),
-    Reader(BufReader<File>)
+    Reader(BufReader<Box<dyn Read>>),
+    Mapped(MappedFile)
+}
+
+impl<R: Read + 'static> From<R> for FileRead {
+    fn from(reader: R) -> FileRead {
+        FileRead::from_reader(reader)
+    }
 }
 
 /// impl block for the FileRead enum
 impl FileRead {
+    /// Wraps any [Read] source in a buffered reader
+    pub fn from_reader<R: Read + 'static>(reader: R) -> FileRead {
+        FileRead::Reader(BufReader::new(Box::new(reader)))
+    }
+
     /// Returns an iterator over the lines of text within the file.
     ///
     /// # Returns
     ///
     /// Returns a boxed iterator that yields `Result<String, io::Error>` for each line.
     /// The iterator handles both cases where `FileRead` is constructed from an existing iterator
-    /// or a direct file reader.
+    /// or a reader.
     ///
     pub fn lines(self) -> Box<dyn Iterator<Item = Result<String, io::Error>>> {
         match self {
             FileRead::Iterator(iter) => iter,
-            FileRead::Reader(reader) => Box::new(reader.lines())
+            FileRead::Reader(reader) => Box::new(reader.lines()),
+            FileRead::Mapped(file) => Box::new(file.into_lines())
+        }
+    }
+
+    /// Returns an iterator over the records of the file, each split into its fields.
+    ///
+    /// Quoted fields are handled by the [tokenizer], so a record may span multiple lines.
+    pub fn records(self, delimiter: char) -> tokenizer::Records<Box<dyn Iterator<Item = Result<String, io::Error>>>> {
+        tokenizer::Records::new(self.lines(), delimiter)
+    }
+}
+
+/// Calls `f` with the index and the fields of every record of `buffer`
+///
+/// The fields of a [FileRead::Mapped] file are borrowed from the map, so a record is not copied unless it has escaped
+/// quotes.
+fn for_each_record(buffer: FileRead, delimiter: char, mut f: impl FnMut(usize, &[Cow<'_, str>]) -> Result<(), Error>) -> Result<(), Error> {
+    match buffer {
+        FileRead::Mapped(file) => {
+            let mut fields = Vec::new();
+            for (index, record) in file.records(delimiter).enumerate() {
+                record?.fields_into(&mut fields)?;
+                f(index, &fields)?;
+            }
+        }
+        buffer => {
+            for (index, record) in buffer.records(delimiter).enumerate() {
+                let fields: Vec<Cow<'_, str>> = record?.into_iter().map(Cow::Owned).collect();
+                f(index, &fields)?;
+            }
         }
     }
+    Ok(())
+}
+
+/// Number of records read and written by a filter, the header excluded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Summary {
+    pub rows_read: usize,
+    pub rows_matched: usize,
 }
 
 /// Filtering module contains functions to filter data from a csv file
 pub mod filtering {
     use super::*;
-    /// Unsafe data filtering function
+    /// Data filtering function searching several fields for the queries
+    ///
+    /// This function will take in a buffer reader and stream to [Command::output_path] (stdout by default) the records
+    /// matching the queries. A query matches a record when it matches one of the searched fields: every field by
+    /// default, or only the fields of the [Command::column] subset, which requires a header. The queries are compared
+    /// to the fields as strings following [Command::match_mode].
     ///
-    /// This function will take in a buffer reader and return a vector of filtered lines
-    /// as strings, where each line contains at least one field that matches the query.
-    /// # Unsafe
-    /// This function can potentially provide unexpected results if the query if there are multiple
-    /// fields in a line that match the query. The first field that matches the query will be
-    /// considered as a match.
-    pub fn any_filter(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata) -> Result<String, std::io::Error> {
-        let mut writer = Vec::new();
-        let querys = &filter_command.query;
-        for (index, line) in buffer.lines().enumerate() {
+    /// With `--query-mode all` ([QueryMode::All], the default) every query must match a searched field, not
+    /// necessarily the same one. With `--query-mode any` ([QueryMode::Any]) a single matching query is enough.
+    /// # Errors
+    /// Returns an [Error::MissingColumn] error if a searched column is not found in the header, and an
+    /// [Error::InvalidArgument] error if a query is not a valid pattern or columns are searched without header.
+    pub fn any_filter(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata) -> Result<Summary, Error> {
+        let output_delimiter = filter_command.output_delimiter.unwrap_or(csv_struct.delimiter);
+        let mut writer = writer::CsvWriter::open(filter_command.output_path.as_deref(), output_delimiter, filter_command.compress)?;
+        let summary = any_filter_to(buffer, filter_command, csv_struct, &mut writer)?;
+        writer.finish()?;
+        Ok(summary)
+    }
+
+    /// Same as [any_filter] but streams the results to `writer` instead of [Command::output_path]
+    pub fn any_filter_to<W: Write>(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut writer::CsvWriter<W>) -> Result<Summary, Error> {
+        any_filter_records(buffer, filter_command, csv_struct, writer, true)
+    }
+
+    /// Same as [any_filter_to], the header being left out of the output when `write_header` is false
+    pub(crate) fn any_filter_records<W: Write>(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut writer::CsvWriter<W>, write_header: bool) -> Result<Summary, Error> {
+        let matchers = filter_command.query
+            .iter()
+            .map(|query| QueryMatcher::new(filter_command.match_mode, query, ColumnType::String))
+            .collect::<Result<Vec<QueryMatcher>, Error>>()?;
+        // Searched column indices, `None` searches every field
+        let mut column_indices: Option<Vec<usize>> = None;
+        let mut output_columns = OutputColumns::new(filter_command, csv_struct)?;
+        let mut summary = Summary::default();
+        if !filter_command.column.is_empty() && !csv_struct.has_header {
+            return Err(Error::InvalidArgument("Searching columns by name requires a header".to_string()));
+        }
+
+        for_each_record(buffer, csv_struct.delimiter, |index, record| {
             if index == 0 && csv_struct.has_header {
-                match line {
-                    Ok(header) => {
-                        writer.push(vec![header]);
-                    }
-                    Err(e) => return Err(e),
+                let header = record;
+                if !filter_command.column.is_empty() {
+                    let indices = filter_command.column
+                        .iter()
+                        .map(|column| header.iter().position(|field| field.trim() == *column).ok_or_else(|| {
+                            Error::MissingColumn(column.clone())
+                        }))
+                        .collect::<Result<Vec<usize>, Error>>()?;
+                    column_indices = Some(indices);
                 }
-            } else{
-                match line {
-                    Ok(content) => {
-                        let mut match_all = true;
-                        for q in querys {
-                            // Split the line by commas and check if any field matches the query
-                            if content.split(',').any(|field| field.trim() == q) {}
-                            else{ match_all = false;}
-                        }
-                        if match_all {
-                            writer.push(vec![content]);
-                        }
-                    }
-                    Err(e) => return Err(e),
+                output_columns.write_header(writer, header, write_header)?;
+            } else {
+                let fields = record;
+                summary.rows_read += 1;
+                // Check if any searched field of the record matches the query
+                let field_matches = |matcher: &QueryMatcher| match &column_indices {
+                    Some(indices) => indices
+                        .iter()
+                        .filter_map(|i| fields.get(*i))
+                        .any(|field| matcher.is_match(field) == Some(true)),
+                    None => fields.iter().any(|field| matcher.is_match(field) == Some(true)),
+                };
+                let is_match = match filter_command.query_mode {
+                    QueryMode::All => matchers.iter().all(field_matches),
+                    QueryMode::Any => matchers.iter().any(field_matches),
+                };
+                if is_match {
+                    output_columns.write_record(writer, fields, index + 1)?;
+                    summary.rows_matched += 1;
                 }
             }
-        }
-        Ok(String::from("SUCCESS"))
+            Ok(())
+        })?;
+        writer.flush()?;
+        Ok(summary)
     }
     /// Safe data filtering function, single column & query matching
     ///
-    /// This function will take in a buffer reader, query and column name and return a vector of filtered lines
-    /// as strings, where each line contains at least one field that matches the query.
-    /// # Limitations
-    /// This function will have undesired results if the csv elements contain commas within double quotes
-    /// For example, due to the limitation of the split function, the following line will be split into 4 fields
-    /// 1,"a,b",2,3 -> [1, "a, b", 2, 3]
+    /// This function will take in a buffer reader, query and column name and stream the matching records
+    /// to [Command::output_path], or stdout when no path is given, so memory stays constant regardless of the input size.
+    /// Records are split by the [tokenizer], so quoted fields keep their commas and line breaks,
+    /// i.e. 1,"a,b",2,3 -> [1, "a,b", 2, 3]
     /// 
     /// Multi-Column filtering supports AND operation, i.e. all the queries must match all the respective columns;queries
-    /// # Panics
-    /// This function will panic if the column name is not found in the csv file
-    pub fn filter(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata) -> Result<String, std::io::Error> {
-        use super::*;
+    /// 
+    /// The `--where` [expression] is evaluated on every record and combined with the column queries using AND.
+    /// 
+    /// Queries are compared following [Command::match_mode]. In the default exact mode, fields and queries are compared
+    /// as typed values following [CsvMetadata::column_types], e.g. `1.0` matches `1` in a `float` column.
+    /// # Errors
+    /// Returns an [Error::TypeConversion] error if a field cannot be converted to its column type, an
+    /// [Error::InvalidArgument] error if a query is not valid, if the columns and queries differ in number or if
+    /// columns are searched without header and an [Error::MissingColumn] error if the column name is not found in the
+    /// csv file.
+    pub fn filter(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata) -> Result<Summary, Error> {
+        let output_delimiter = filter_command.output_delimiter.unwrap_or(csv_struct.delimiter);
+        // Matching records are streamed to the output as they are read
+        let mut writer = writer::CsvWriter::open(filter_command.output_path.as_deref(), output_delimiter, filter_command.compress)?;
+        let summary = filter_to(buffer, filter_command, csv_struct, &mut writer)?;
+        writer.finish()?;
+        Ok(summary)
+    }
 
-        let mut writer: Vec<Vec<String>> = Vec::new();
+    /// Same as [filter] but streams the results to `writer` instead of [Command::output_path]
+    /// # Example
+    /// ```
+    /// # use sdk::data::manipulation::{filtering, Command, FileRead, Subcommand};
+    /// # use sdk::reader::CsvMetadata;
+    /// # use sdk::writer::CsvWriter;
+    /// let csv_struct = CsvMetadata {
+    ///     file: "in-memory.csv".to_string(),
+    ///     ..CsvMetadata::default()
+    /// };
+    /// let command = Command {
+    ///     query: vec!["1".to_string()],
+    ///     column: vec!["key".to_string()],
+    ///     subcommand: Subcommand::Filter(csv_struct.clone()),
+    ///     ..Command::default()
+    /// };
+    /// let buffer = FileRead::from_reader(std::io::Cursor::new("key,val\n1,a\n2,b\n"));
+    /// let mut writer = CsvWriter::new(Vec::new(), ',');
+    /// let summary = filtering::filter_to(buffer, &command, &csv_struct, &mut writer).unwrap();
+    /// assert_eq!((summary.rows_read, summary.rows_matched), (2, 1));
+    /// assert_eq!(writer.into_inner(), b"key,val\n1,a\n");
+    /// ```
+    pub fn filter_to<W: Write>(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut writer::CsvWriter<W>) -> Result<Summary, Error> {
+        filter_records(buffer, filter_command, csv_struct, writer, true)
+    }
+
+    /// Same as [filter_to], the header being left out of the output when `write_header` is false
+    pub(crate) fn filter_records<W: Write>(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut writer::CsvWriter<W>, write_header: bool) -> Result<Summary, Error> {
         let columns: &Vec<String> = &filter_command.column;
         let queries: &Vec<String> = &filter_command.query;
-        let output_path = &filter_command.output_path;
-
-        println!("Columns: {:?}", columns);
-        println!("Queries: {:?}", queries);
 
+        let schema = Schema::from_column_types(&csv_struct.column_types)?;
         let mut column_indices = Vec::new();
+        let mut output_columns = OutputColumns::new(filter_command, csv_struct)?;
+        let mut summary = Summary::default();
+        let mut matchers: Vec<QueryMatcher> = Vec::new();
+        if columns.len() != queries.len() {
+            return Err(Error::InvalidArgument(format!("{} columns given for {} queries", columns.len(), queries.len())));
+        }
+        if !columns.is_empty() && !csv_struct.has_header {
+            return Err(Error::InvalidArgument("Searching columns by name requires a header".to_string()));
+        }
+        let mut where_clause = match &filter_command.where_clause {
+            Some(source) if csv_struct.has_header => Some(expression::parse(source)?),
+            Some(source) => Some(expression::parse(source)?.resolve(None)?),
+            None => None,
+        };
 
-        for (index, line) in buffer.lines().enumerate() {
+        for_each_record(buffer, csv_struct.delimiter, |index, record| {
             if index == 0 && csv_struct.has_header {
                 // find the column indices
-                match line {
-                    Ok(header) => {
-                        let headers: Vec<&str> = header.split(',').collect();
-                        for column in columns {
-                            match headers.iter().position(|&field| field.trim() == *column) {
-                                Some(col_index) => column_indices.push(col_index),
-                                None => panic!("\x1b[0;31mRuntime Panic:\x1b[0m Column {} not found in the csv file", column),
-                            }
-                        }
-                        writer.push(vec![header]);
+                let header: Vec<String> = record.iter().map(|field| field.to_string()).collect();
+                for (column, query) in columns.iter().zip(queries) {
+                    match header.iter().position(|field| field.trim() == *column) {
+                        Some(col_index) => {
+                            let matcher = QueryMatcher::new(filter_command.match_mode, query, schema.column_type(col_index))
+                                .map_err(|e| Error::InvalidArgument(format!("{} for column {}", e, column)))?;
+                            matchers.push(matcher);
+                            column_indices.push(col_index)
+                        },
+                        None => return Err(Error::MissingColumn(column.clone())),
                     }
-                    Err(e) => return Err(e),
                 }
+                where_clause = where_clause.take().map(|expr| expr.resolve(Some(&header))).transpose()?;
+                output_columns.write_header(writer, &header, write_header)?;
             } else {
-                match line {
-                    Ok(record) => {
-                        let fields: Vec<&str> = record.split(',').collect();
-                        let mut match_all = true;
-                        for (col_index, query) in column_indices.iter().zip(queries) {
-                            if fields[*col_index].trim() != *query {
-                                match_all = false;
-                                break;
-                            }
-                        }
-                        if match_all {
-                            writer.push(vec![record]);
-                        }
+                let fields = record;
+                summary.rows_read += 1;
+                let mut match_all = true;
+                for (col_index, matcher) in column_indices.iter().zip(&matchers) {
+                    // Records with missing trailing fields never match
+                    let is_match = match fields.get(*col_index) {
+                        Some(field) => match matcher.is_match(field) {
+                            Some(is_match) => is_match,
+                            None => return Err(ConversionError {
+                                row: index + 1,
+                                column: *col_index,
+                                value: field.to_string(),
+                                column_type: schema.column_type(*col_index),
+                            }.into()),
+                        },
+                        None => false,
+                    };
+                    if !is_match {
+                        match_all = false;
+                        break;
                     }
-                    Err(e) => return Err(e),
+                }
+                if let (true, Some(expr)) = (match_all, &where_clause) {
+                    match_all = expr.is_match(fields, &schema, index + 1)?;
+                }
+                if match_all {
+                    output_columns.write_record(writer, fields, index + 1)?;
+                    summary.rows_matched += 1;
                 }
             }
-            match &output_path {
-                Some(path) => {
-                    let _ = writer::csv_writer(path.clone(), writer.clone());
-                },
-                None => {}
-            }
-        }
-        Ok(String::from("SUCCESS"))
+            Ok(())
+        })?;
+        writer.flush()?;
+        Ok(summary)
     }
 }
 
@@ -182,18 +377,16 @@ mod tests {
         path.push("test/example/data.csv");
         let csv_handler = CsvMetadata {
             file: path.to_str().unwrap().to_string(),
-            delimiter: ',',
-            has_header: true,
-            column_types: vec!["string".to_string()]
+            ..CsvMetadata::default()
         };
         let filter_command = Command {
             query: vec!["1".to_string()],
             column: vec!["key".to_string()],
-            output_path: None,
-            subcommand: Subcommand::Filter(csv_handler.clone())
+            subcommand: Subcommand::Filter(csv_handler.clone()),
+            ..Command::default()
         };
         let file = std::fs::File::open(path).unwrap();
-        let reader = FileRead::Reader(BufReader::new(file));
+        let reader = FileRead::from_reader(file);
         let writer = filtering::filter(reader, &filter_command, &csv_handler);
         assert!(writer.is_ok());
     }
@@ -204,18 +397,257 @@ mod tests {
         path.push("test/*/*.csv");
         let csv_handler = CsvMetadata {
             file: path.to_str().unwrap().to_string(),
-            delimiter: ',',
-            has_header: true,
-            column_types: vec!["string".to_string()]
+            ..CsvMetadata::default()
         };
         let filter_command = Command {
             query: vec!["1".to_string()],
             column: vec!["key".to_string()],
-            output_path: None,
-            subcommand: Subcommand::Filter(csv_handler.clone())
+            subcommand: Subcommand::Filter(csv_handler.clone()),
+            ..Command::default()
         };
-        let reader = crate::reader::glob_reader(&csv_handler);
+        let reader = crate::reader::glob_reader(&csv_handler).unwrap();
         let writer = filtering::filter(reader, &filter_command, &csv_handler);
         assert!(writer.is_ok());
     }
+
+    fn in_memory(delimiter: char, column_types: &[&str]) -> CsvMetadata {
+        CsvMetadata {
+            file: "in-memory.csv".to_string(),
+            delimiter,
+            column_types: column_types.iter().map(|t| t.to_string()).collect(),
+            ..CsvMetadata::default()
+        }
+    }
+
+    fn command(csv_handler: &CsvMetadata, column: &[&str], query: &[&str]) -> Command {
+        Command {
+            query: query.iter().map(|q| q.to_string()).collect(),
+            column: column.iter().map(|c| c.to_string()).collect(),
+            subcommand: Subcommand::Filter(csv_handler.clone()),
+            ..Command::default()
+        }
+    }
+
+    /// Runs the filter selected by the command subcommand over in-memory data and returns the written output
+    fn run(data: &str, filter_command: &Command, csv_handler: &CsvMetadata) -> Result<String, Error> {
+        let reader = FileRead::from_reader(io::Cursor::new(data.to_string()));
+        let delimiter = filter_command.output_delimiter.unwrap_or(csv_handler.delimiter);
+        let mut writer = writer::CsvWriter::new(Vec::new(), delimiter);
+        match filter_command.subcommand {
+            Subcommand::Filter(_) => filtering::filter_to(reader, filter_command, csv_handler, &mut writer)?,
+            Subcommand::Anyfilter(_) => filtering::any_filter_to(reader, filter_command, csv_handler, &mut writer)?,
+        };
+        Ok(String::from_utf8(writer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_filtering_quoted_fields() {
+        let csv_handler = in_memory(',', &["string"]);
+        let filter_command = command(&csv_handler, &["key"], &["1"]);
+        let written = run("key,val\n1,\"a,b\"\n2,\"multi\nline\"\n\"1\",c", &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "key,val\n1,\"a,b\"\n1,c\n");
+    }
+
+    #[test]
+    fn test_filtering_unquoted_quotes() {
+        let data = "id,h,n\n1,5'10\",bob\n2,6ft,amy\n3,5ft,joe";
+        let csv_handler = in_memory(',', &["int"]);
+        let written = run(data, &command(&csv_handler, &["id"], &["2"]), &csv_handler).unwrap();
+        assert_eq!(written, "id,h,n\n2,6ft,amy\n");
+        let written = run(data, &command(&csv_handler, &["id"], &["1"]), &csv_handler).unwrap();
+        assert_eq!(written, "id,h,n\n1,\"5'10\"\"\",bob\n");
+    }
+
+    #[test]
+    fn test_filtering_delimiters() {
+        let csv_handler = in_memory('\t', &["string"]);
+        let mut filter_command = command(&csv_handler, &["key"], &["1"]);
+        filter_command.output_delimiter = Some('|');
+        let written = run("key\tval\n1\ta|b\n2\tc", &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "key|val\n1|\"a|b\"\n");
+    }
+
+    #[test]
+    fn test_filtering_typed_columns() {
+        let csv_handler = in_memory(',', &["int", "float"]);
+        let filter_command = command(&csv_handler, &["price"], &["1.5"]);
+        let written = run("key,price\n01,1.50\n2,1.5\n3,2", &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "key,price\n01,1.50\n2,1.5\n");
+
+        let error = run("key,price\n1,abc", &filter_command, &csv_handler).unwrap_err();
+        assert!(error.to_string().contains("row 2, column 1"));
+        assert!(matches!(error, Error::TypeConversion(_)));
+    }
+
+    #[test]
+    fn test_filtering_errors() {
+        let csv_handler = in_memory(',', &["string"]);
+        let filter_command = command(&csv_handler, &["missing"], &["1"]);
+        let error = run("key,val\n1,a", &filter_command, &csv_handler).unwrap_err();
+        assert!(matches!(error, Error::MissingColumn(column) if column == "missing"));
+
+        let filter_command = command(&csv_handler, &["key"], &["1"]);
+        let error = run("key,val\n1,a\n2,\"b", &filter_command, &csv_handler).unwrap_err();
+        assert!(matches!(error, Error::MalformedRecord { line: 3, byte: 14, .. }));
+    }
+
+    #[test]
+    fn test_filtering_where_expression() {
+        let csv_handler = in_memory(',', &["string"]);
+        let mut filter_command = command(&csv_handler, &[], &[]);
+        filter_command.where_clause = Some("age >= 30 && (state == \"CA\" || state == \"NY\")".to_string());
+        let written = run("name,age,state\na,31,CA\nb,25,NY\nc,40,TX\nd,52,NY", &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "name,age,state\na,31,CA\nd,52,NY\n");
+    }
+
+    #[test]
+    fn test_filtering_projection() {
+        let data = "id,name,addr_city,addr_zip\n1,a,Paris,75001\n2,b,Lyon\n";
+        let mut csv_handler = in_memory(',', &["string"]);
+        let mut filter_command = command(&csv_handler, &[], &[]);
+        filter_command.select = vec!["/^addr_/".to_string(), "$1".to_string()];
+        filter_command.drop = vec!["addr_city".to_string()];
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "addr_zip,id\n75001,1\n,2\n");
+
+        filter_command.subcommand = Subcommand::Anyfilter(csv_handler.clone());
+        filter_command.query = vec!["Lyon".to_string()];
+        filter_command.select = vec!["$3-$4".to_string(), "name".to_string()];
+        filter_command.drop = vec![];
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "addr_city,addr_zip,name\nLyon,,b\n");
+
+        filter_command.query = vec!["Paris".to_string()];
+        filter_command.rename = vec!["addr_city=city".to_string()];
+        filter_command.with = vec!["label=concat(upper(city), ' ', addr_zip)".to_string()];
+        filter_command.select = vec!["id".to_string(), "label".to_string()];
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "id,label\n1,PARIS 75001\n");
+
+        csv_handler.has_header = false;
+        filter_command.rename = vec![];
+        filter_command.with = vec![];
+        filter_command.select = vec!["name".to_string()];
+        let error = run(data, &filter_command, &csv_handler).unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_filtering_canonical_output() {
+        let csv_handler = in_memory(',', &["string"]);
+        let filter_command = command(&csv_handler, &["key"], &["1"]);
+        let written = run("key,val\r\n\"1\",\"a\r\nb\"\r\n\"1\",\"x\"\"y\"\r\n", &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "key,val\n1,\"a\nb\"\n1,\"x\"\"y\"\n");
+    }
+
+    #[test]
+    fn test_filtering_column_query_count() {
+        let csv_handler = in_memory(',', &["string"]);
+        let filter_command = command(&csv_handler, &["key", "val"], &["1"]);
+        let error = run("key,val\n1,a\n", &filter_command, &csv_handler).unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
+
+        let filter_command = command(&csv_handler, &["key"], &["1", "a"]);
+        let error = run("key,val\n1,a\n", &filter_command, &csv_handler).unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_filtering_columns_without_header() {
+        let csv_handler = CsvMetadata { has_header: false, ..in_memory(',', &["string"]) };
+        let filter_command = command(&csv_handler, &["key"], &["1"]);
+        let error = run("1,a\n2,b\n", &filter_command, &csv_handler).unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_filtering_match_modes() {
+        let data = "email,sku\njane@example.com,AB-1234\njoe@example.org,AB-12\nann@example.com,CD-5678";
+        let csv_handler = in_memory(',', &["string"]);
+        let mut filter_command = command(&csv_handler, &["email"], &["*@example.com"]);
+        filter_command.match_mode = MatchMode::Glob;
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "email,sku\njane@example.com,AB-1234\nann@example.com,CD-5678\n");
+
+        let mut filter_command = command(&csv_handler, &["sku"], &[r"^AB-\d{4}$"]);
+        filter_command.match_mode = MatchMode::Regex;
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "email,sku\njane@example.com,AB-1234\n");
+    }
+
+    #[test]
+    fn test_any_filter_output() {
+        let data = "name,city,note\nann,Paris,lyon trip\nbob,Lyon,paris trip\ncid,Rome,none";
+        let csv_handler = in_memory(',', &["string"]);
+        let mut filter_command = command(&csv_handler, &["city"], &["paris", "lyon"]);
+        filter_command.subcommand = Subcommand::Anyfilter(csv_handler.clone());
+        filter_command.match_mode = MatchMode::CaseInsensitive;
+        filter_command.query_mode = QueryMode::Any;
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "name,city,note\nann,Paris,lyon trip\nbob,Lyon,paris trip\n");
+
+        // Every query must now match one of the searched columns
+        filter_command.column = vec![];
+        filter_command.match_mode = MatchMode::Substring;
+        filter_command.query_mode = QueryMode::All;
+        filter_command.query = vec!["Paris".to_string(), "lyon".to_string()];
+        let written = run(data, &filter_command, &csv_handler).unwrap();
+        assert_eq!(written, "name,city,note\nann,Paris,lyon trip\n");
+    }
+
+    #[test]
+    fn test_any_filter_writes_output_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output.csv");
+        let csv_handler = in_memory(',', &["string"]);
+        let mut filter_command = command(&csv_handler, &[], &["b"]);
+        filter_command.output_path = Some(output.to_str().unwrap().to_string());
+        let reader = FileRead::from(io::Cursor::new("key,val\n1,a\n2,b\n"));
+        let summary = filtering::any_filter(reader, &filter_command, &csv_handler).unwrap();
+        assert_eq!(summary, Summary { rows_read: 2, rows_matched: 1 });
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "key,val\n2,b\n");
+    }
+
+    #[test]
+    fn test_filtering_compressed_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output.csv.gz");
+        let csv_handler = in_memory(',', &["string"]);
+        let mut filter_command = command(&csv_handler, &["key"], &["2"]);
+        filter_command.output_path = Some(output.to_str().unwrap().to_string());
+        let reader = FileRead::from(io::Cursor::new("key,val\n1,a\n2,b\n"));
+        assert!(filtering::filter(reader, &filter_command, &csv_handler).is_ok());
+        let mut written = String::new();
+        crate::compression::open(&output).unwrap().read_to_string(&mut written).unwrap();
+        assert_eq!(written, "key,val\n2,b\n");
+    }
+
+    #[test]
+    fn test_mapped_filtering() {
+        let data = "id,state,note\n1,CA,\"a,\nb\"\n2,NY,\"say \"\"hi\"\"\"\r\n3,CA,plain\n";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mapped.csv");
+        std::fs::write(&path, data).unwrap();
+        let mut csv_handler = in_memory(',', &["int"]);
+        csv_handler.file = path.to_str().unwrap().to_string();
+        csv_handler.mmap = true;
+        let mut filter_command = command(&csv_handler, &["state"], &["CA"]);
+        filter_command.where_clause = Some("id > 1 || note contains \"\n\"".to_string());
+        let mut anyfilter_command = command(&csv_handler, &[], &["hi"]);
+        anyfilter_command.match_mode = MatchMode::Substring;
+        anyfilter_command.subcommand = Subcommand::Anyfilter(csv_handler.clone());
+
+        for filter_command in [&filter_command, &anyfilter_command] {
+            let buffer = crate::loader(&csv_handler).unwrap();
+            assert!(matches!(buffer, FileRead::Mapped(_)));
+            let mut writer = writer::CsvWriter::new(Vec::new(), ',');
+            match filter_command.subcommand {
+                Subcommand::Filter(_) => filtering::filter_to(buffer, filter_command, &csv_handler, &mut writer).unwrap(),
+                Subcommand::Anyfilter(_) => filtering::any_filter_to(buffer, filter_command, &csv_handler, &mut writer).unwrap(),
+            };
+            let mapped = String::from_utf8(writer.into_inner()).unwrap();
+            assert_eq!(mapped, run(data, filter_command, &csv_handler).unwrap());
+        }
+        assert_eq!(run(data, &filter_command, &csv_handler).unwrap(), "id,state,note\n1,CA,\"a,\nb\"\n3,CA,plain\n");
+    }
 }
@@ -0,0 +1,250 @@
+//! Zero-copy records of a memory mapped csv file
+//!
+//! [MappedFile] maps a file in memory and [RecordSlices] yields its records as [RecordSlice]s borrowing the map, so
+//! no `String` is allocated per line. The fields of a record are located as byte ranges and decoded on demand,
+//! only fields with escaped quotes being copied. The quoting rules are the ones of the [tokenizer](super::tokenizer).
+//!
+//! # Example
+//! ```
+//! use sdk::data::mapped::RecordSlices;
+//! let mut records = RecordSlices::new(b"key,val\n1,\"a,b\"\n", ',');
+//! let header = records.next().unwrap().unwrap();
+//! assert_eq!(header.as_str(), "key,val");
+//! let mut fields = Vec::new();
+//! records.next().unwrap().unwrap().fields_into(&mut fields).unwrap();
+//! assert_eq!(fields, vec!["1", "a,b"]);
+//! ```
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use memmap2::Mmap;
+use super::scanner::Scanner;
+use super::tokenizer::{self, QuoteState, RecordEnds};
+use crate::Error;
+
+/// A csv file mapped in memory
+///
+/// The file must not be truncated while it is mapped, reading the missing pages would abort the process.
+pub struct MappedFile {
+    map: Mmap,
+}
+
+impl MappedFile {
+    /// Maps the file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedFile, Error> {
+        let file = File::open(&path).map_err(Error::file(&path))?;
+        // SAFETY: the map is read only, see the note on truncation above
+        let map = unsafe { Mmap::map(&file).map_err(Error::file(&path))? };
+        Ok(MappedFile { map })
+    }
+
+    /// Returns the content of the file
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Returns an iterator over the records of the file, fields being separated by `delimiter`
+    pub fn records(&self, delimiter: char) -> RecordSlices<'_> {
+        RecordSlices::new(&self.map, delimiter)
+    }
+
+    /// Returns an iterator over the lines of the file without their `\n` or `\r\n` terminator, like [io::BufRead::lines]
+    pub fn into_lines(self) -> impl Iterator<Item = Result<String, io::Error>> {
+        let mut position = 0;
+        std::iter::from_fn(move || {
+            let data = self.as_bytes();
+            if position >= data.len() {
+                return None;
+            }
+            let end = data[position..].iter().position(|b| *b == b'\n').map_or(data.len(), |i| position + i);
+            let line = &data[position..end];
+            position = end + 1;
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            Some(std::str::from_utf8(line).map(str::to_string).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        })
+    }
+}
+
+/// Iterator over the records of csv data, see [MappedFile::records]
+///
+/// A record ends at the first line break outside of a quoted field, its `\n` or `\r\n` terminator being dropped.
+pub struct RecordSlices<'a> {
+    data: &'a [u8],
+    delimiter: char,
+    /// Offset of the next record
+    position: usize,
+    /// 1-based line of the next record
+    line: usize,
+    /// Finds the line breaks ending records
+    ends: RecordEnds,
+    /// Counts the line breaks within records
+    newlines: Scanner,
+}
+
+impl<'a> RecordSlices<'a> {
+    /// Iterates over the records of `data`
+    pub fn new(data: &'a [u8], delimiter: char) -> RecordSlices<'a> {
+        RecordSlices {
+            data,
+            delimiter,
+            position: 0,
+            line: 1,
+            ends: RecordEnds::new(delimiter),
+            newlines: Scanner::new(b"\n"),
+        }
+    }
+
+    /// 1-based line number the next record starts at
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl<'a> Iterator for RecordSlices<'a> {
+    type Item = Result<RecordSlice<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.position;
+        if start >= self.data.len() {
+            return None;
+        }
+        let line = self.line;
+        let mut state = QuoteState::START;
+        let end = self.ends.find(self.data, start, &mut state).unwrap_or(self.data.len());
+        self.line += self.newlines.count(&self.data[start..end]) + usize::from(end < self.data.len());
+        self.position = end + 1;
+        let bytes = &self.data[start..end];
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        Some(match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(RecordSlice { text, delimiter: self.delimiter, line, byte: start as u64 }),
+            Err(e) => Err(Error::MalformedRecord {
+                line: line + bytes[..e.valid_up_to()].iter().filter(|b| **b == b'\n').count(),
+                byte: (start + e.valid_up_to()) as u64,
+                message: e.to_string(),
+            }),
+        })
+    }
+}
+
+/// A record borrowed from the csv data
+#[derive(Debug, Clone, Copy)]
+pub struct RecordSlice<'a> {
+    text: &'a str,
+    delimiter: char,
+    line: usize,
+    byte: u64,
+}
+
+impl<'a> RecordSlice<'a> {
+    /// Raw text of the record, quotes included and line terminator excluded
+    pub fn as_str(&self) -> &'a str {
+        self.text
+    }
+
+    /// 1-based line the record starts at
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Offset of the record in the data
+    pub fn byte(&self) -> u64 {
+        self.byte
+    }
+
+    /// Replaces the content of `ranges` with the byte ranges of the fields in [RecordSlice::as_str], quotes included
+    /// # Errors
+    /// Returns an [Error::MalformedRecord] error, positioned at the opening quote, if a quoted field is never closed.
+    pub fn field_ranges(&self, ranges: &mut Vec<Range<usize>>) -> Result<(), Error> {
+        ranges.clear();
+        self.for_each_field(|range| ranges.push(range))
+    }
+
+    /// Replaces the content of `fields` with the decoded fields of the record, see [RecordSlice::field]
+    ///
+    /// Reusing `fields` across records avoids any allocation for fields without escaped quotes.
+    /// # Errors
+    /// Same as [RecordSlice::field_ranges].
+    pub fn fields_into(&self, fields: &mut Vec<Cow<'a, str>>) -> Result<(), Error> {
+        fields.clear();
+        self.for_each_field(|range| fields.push(self.field(range)))
+    }
+
+    /// Decodes the field at `range`, one of the [RecordSlice::field_ranges], like [tokenizer::split_record]
+    ///
+    /// The field is borrowed unless it has escaped quotes, text around its quotes or `\r\n` line breaks.
+    pub fn field(&self, range: Range<usize>) -> Cow<'a, str> {
+        let raw = &self.text[range];
+        match raw.contains('\r') {
+            // Line breaks are `\n` once a record is read line by line
+            true => Cow::Owned(tokenizer::decode_field(&raw.replace("\r\n", "\n")).into_owned()),
+            false => tokenizer::decode_field(raw),
+        }
+    }
+
+    /// Calls `f` with the range of every field
+    fn for_each_field(&self, f: impl FnMut(Range<usize>)) -> Result<(), Error> {
+        tokenizer::field_ranges(self.text, self.delimiter, f).map_err(|quote_start| match tokenizer::unterminated(self.text, quote_start) {
+            Error::MalformedRecord { line, byte, message } => Error::MalformedRecord {
+                line: self.line + line - 1,
+                byte: self.byte + byte,
+                message,
+            },
+            e => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(data: &str, delimiter: char) -> Vec<Result<Vec<String>, Error>> {
+        RecordSlices::new(data.as_bytes(), delimiter)
+            .map(|record| {
+                let mut fields = Vec::new();
+                record?.fields_into(&mut fields)?;
+                Ok(fields.into_iter().map(Cow::into_owned).collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_tokenizer() {
+        let data = "id,note\r\n1,\"a,\r\nb\"\n2,\"say \"\"hi\"\"\"\n3,  \"x\" y\n4,a\"\"b\n\n5, é ,\"\u{a0}\"\n6,5'10\"\n7,\"\"";
+        let lines = data.lines().map(|line| Ok(line.to_string()));
+        let expected: Vec<Vec<String>> = tokenizer::Records::new(lines, ',').map(Result::unwrap).collect();
+        let found: Vec<Vec<String>> = fields(data, ',').into_iter().map(Result::unwrap).collect();
+        assert_eq!(found, expected);
+        assert_eq!(found[1], vec!["1", "a,\nb"]);
+        assert_eq!(found[7], vec!["6", "5'10\""]);
+        let lines: Vec<usize> = RecordSlices::new(data.as_bytes(), ',').map(|record| record.unwrap().line()).collect();
+        assert_eq!(lines, vec![1, 2, 4, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn test_borrowed_fields() {
+        let mut records = RecordSlices::new(b"1,\"a,b\",\"c\"\"d\"\n", ',');
+        let record = records.next().unwrap().unwrap();
+        let mut fields = Vec::new();
+        record.fields_into(&mut fields).unwrap();
+        assert!(matches!(fields[0], Cow::Borrowed("1")));
+        assert!(matches!(fields[1], Cow::Borrowed("a,b")));
+        assert!(matches!(&fields[2], Cow::Owned(field) if field == "c\"d"));
+        let mut ranges = Vec::new();
+        record.field_ranges(&mut ranges).unwrap();
+        assert_eq!(ranges, vec![0..1, 2..7, 8..14]);
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn test_malformed_record_position() {
+        let records = fields("key,val\n1,a\n2,\"b\n3,c\n", ',');
+        assert!(matches!(records[2], Err(Error::MalformedRecord { line: 3, byte: 14, .. })));
+        let records = fields("key\n\u{fffd}\n", ',');
+        assert!(records.iter().all(Result::is_ok));
+        let records: Vec<_> = RecordSlices::new(b"key\nab\xff\n", ',').collect();
+        assert!(matches!(records[1], Err(Error::MalformedRecord { line: 2, byte: 6, .. })));
+    }
+}
@@ -0,0 +1,122 @@
+//! Query matching modes used by the column/query filters
+//!
+//! A `--query` is compiled once into a [QueryMatcher] following the `--match-mode` of the [Command](super::manipulation::Command)
+//! and then tested against the trimmed field of every record.
+//!
+//! # Example
+//! ```
+//! use sdk::data::matching::{MatchMode, QueryMatcher};
+//! use sdk::data::schema::ColumnType;
+//! let matcher = QueryMatcher::new(MatchMode::Glob, "*@example.com", ColumnType::String).unwrap();
+//! assert_eq!(matcher.is_match("jane@example.com"), Some(true));
+//! ```
+use clap::ValueEnum;
+use glob::Pattern;
+use regex::Regex;
+use super::schema::{ColumnType, Value};
+use crate::Error;
+
+/// How a query is compared to a field
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Typed equality following the column types
+    #[default]
+    Exact,
+    /// String equality ignoring case
+    CaseInsensitive,
+    /// Shell style wildcards, `*`, `?` and `[...]`
+    Glob,
+    /// Regular expression, unanchored unless the query uses `^` and `$`
+    Regex,
+    /// Field contains the query
+    Substring,
+}
+
+/// How the results of several queries are combined
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryMode {
+    /// Every query must match
+    #[default]
+    All,
+    /// At least one query must match
+    Any,
+}
+
+/// A query compiled for a [MatchMode]
+#[derive(Debug, Clone)]
+pub enum QueryMatcher {
+    Exact(Value, ColumnType),
+    CaseInsensitive(String),
+    Glob(Pattern),
+    Regex(Regex),
+    Substring(String),
+}
+
+impl QueryMatcher {
+    /// Compiles `query` for `mode`, `column_type` is only used by [MatchMode::Exact]
+    /// # Errors
+    /// Returns an [Error::InvalidArgument] error if the query is not a valid pattern or, in exact mode, not a
+    /// valid value of the column type.
+    pub fn new(mode: MatchMode, query: &str, column_type: ColumnType) -> Result<QueryMatcher, Error> {
+        let invalid = Error::InvalidArgument;
+        let query = query.trim();
+        Ok(match mode {
+            MatchMode::Exact => match column_type.parse(query) {
+                Some(value) => QueryMatcher::Exact(value, column_type),
+                None => return Err(invalid(format!("Query {} is not a valid {}", query, column_type))),
+            },
+            MatchMode::CaseInsensitive => QueryMatcher::CaseInsensitive(query.to_lowercase()),
+            MatchMode::Glob => Pattern::new(query)
+                .map(QueryMatcher::Glob)
+                .map_err(|e| invalid(format!("Invalid glob pattern {}: {}", query, e)))?,
+            MatchMode::Regex => Regex::new(query)
+                .map(QueryMatcher::Regex)
+                .map_err(|e| invalid(format!("Invalid regex {}: {}", query, e)))?,
+            MatchMode::Substring => QueryMatcher::Substring(query.to_string()),
+        })
+    }
+
+    /// Tests a raw field against the query
+    ///
+    /// Returns `None` when, in exact mode, the field cannot be converted to the column type.
+    pub fn is_match(&self, field: &str) -> Option<bool> {
+        let field = field.trim();
+        match self {
+            QueryMatcher::Exact(query, column_type) => column_type.parse(field).map(|value| value.matches(query)),
+            QueryMatcher::CaseInsensitive(query) => Some(field.to_lowercase() == *query),
+            QueryMatcher::Glob(pattern) => Some(pattern.matches(field)),
+            QueryMatcher::Regex(regex) => Some(regex.is_match(field)),
+            QueryMatcher::Substring(query) => Some(field.contains(query.as_str())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(mode: MatchMode, query: &str, field: &str) -> bool {
+        QueryMatcher::new(mode, query, ColumnType::String).unwrap().is_match(field).unwrap()
+    }
+
+    #[test]
+    fn test_match_modes() {
+        assert!(matches(MatchMode::Exact, "CA", " CA "));
+        assert!(!matches(MatchMode::Exact, "CA", "ca"));
+        assert!(matches(MatchMode::CaseInsensitive, "CA", "ca"));
+        assert!(matches(MatchMode::Glob, "*@example.com", "jane@example.com"));
+        assert!(!matches(MatchMode::Glob, "*@example.com", "jane@example.org"));
+        assert!(matches(MatchMode::Regex, r"^AB-\d{4}$", "AB-1234"));
+        assert!(!matches(MatchMode::Regex, r"^AB-\d{4}$", "AB-12345"));
+        assert!(matches(MatchMode::Substring, "exam", "jane@example.com"));
+    }
+
+    #[test]
+    fn test_exact_typed() {
+        let matcher = QueryMatcher::new(MatchMode::Exact, "1", ColumnType::Float).unwrap();
+        assert_eq!(matcher.is_match("1.0"), Some(true));
+        assert_eq!(matcher.is_match("abc"), None);
+        assert!(QueryMatcher::new(MatchMode::Exact, "abc", ColumnType::Int).is_err());
+        assert!(QueryMatcher::new(MatchMode::Regex, "(", ColumnType::String).is_err());
+    }
+}
@@ -0,0 +1,695 @@
+//! Concurrent filtering of the files matched by a glob pattern or of the byte ranges of a single large file
+//!
+//! [filter_files] filters every file of [CsvMetadata::file] on a [rayon] thread pool. Each worker reads one file,
+//! aligns it to the common header like [glob_reader](crate::reader::glob_reader) and filters it in memory, the outputs
+//! being merged into a single writer by the calling thread. The output of a file is written as soon as it completes,
+//! or after the output of every file before it with [Command::preserve_order].
+//!
+//! [filter_chunks] splits a single file into byte ranges starting at record boundaries and filters them the same way,
+//! always writing the records in the order of the file.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::sync::mpsc;
+use rayon::prelude::*;
+use super::computed::OutputColumns;
+use super::manipulation::{filtering, Command, FileRead, Subcommand, Summary};
+use super::scanner::Scanner;
+use super::tokenizer::{self, QuoteState};
+use super::super::compression::Compression;
+use super::super::reader::{self, CsvMetadata};
+use super::super::writer::CsvWriter;
+use crate::Error;
+
+/// Serialized matching records of a file and its counts
+type FileOutput = (Vec<u8>, Summary);
+
+/// Filters the files of a glob pattern concurrently and streams the results to [Command::output_path], stdout by default
+///
+/// The filter is selected by [Command::subcommand] and the pool has [Command::threads] threads, one per core when
+/// unset or `0`.
+/// # Errors
+/// Returns the first error of a file, the files not started yet are skipped. With [Command::preserve_order] the error
+/// is the one of the first failing file in the order of the glob. See [filtering::filter] and
+/// [glob_reader](crate::reader::glob_reader) for the errors of a file.
+pub fn filter_files(filter_command: &Command, csv_struct: &CsvMetadata) -> Result<Summary, Error> {
+    let output_delimiter = filter_command.output_delimiter.unwrap_or(csv_struct.delimiter);
+    let mut writer = CsvWriter::open(filter_command.output_path.as_deref(), output_delimiter, filter_command.compress)?;
+    let summary = filter_files_to(filter_command, csv_struct, &mut writer)?;
+    writer.finish()?;
+    Ok(summary)
+}
+
+/// Same as [filter_files] but streams the results to `writer` instead of [Command::output_path]
+pub fn filter_files_to<W: Write>(filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut CsvWriter<W>) -> Result<Summary, Error> {
+    let paths = reader::glob_paths(csv_struct)?;
+    let header = reader::common_header(&paths, csv_struct)?;
+    let output_delimiter = filter_command.output_delimiter.unwrap_or(csv_struct.delimiter);
+    let pool = thread_pool(filter_command)?;
+    if let Some(header) = &header {
+        let columns = reader::header_columns(csv_struct, header);
+        OutputColumns::new(filter_command, csv_struct)?.write_header(writer, &columns, true)?;
+    }
+    let header = header.as_deref();
+    merge(&pool, &paths, filter_command.preserve_order, writer, |_, path| {
+        filter_file(path, filter_command, csv_struct, header, output_delimiter)
+    })
+}
+
+/// Filters a single file concurrently and streams the results to [Command::output_path], stdout by default
+///
+/// The file is split into byte ranges that are filtered on a pool of [Command::threads] threads, the records being
+/// written in the order of the file. The ranges are moved to record boundaries by a first concurrent pass counting the
+/// quotes and newlines of the file, so quoted fields spanning several lines are never cut. Compressed files cannot be
+/// split and are filtered sequentially.
+/// # Errors
+/// Same as [filtering::filter], the positions of a [Error::MalformedRecord] or [Error::TypeConversion] error being
+/// relative to the start of the file.
+pub fn filter_chunks(filter_command: &Command, csv_struct: &CsvMetadata) -> Result<Summary, Error> {
+    let output_delimiter = filter_command.output_delimiter.unwrap_or(csv_struct.delimiter);
+    let mut writer = CsvWriter::open(filter_command.output_path.as_deref(), output_delimiter, filter_command.compress)?;
+    let summary = filter_chunks_to(filter_command, csv_struct, &mut writer)?;
+    writer.finish()?;
+    Ok(summary)
+}
+
+/// Same as [filter_chunks] but streams the results to `writer` instead of [Command::output_path]
+pub fn filter_chunks_to<W: Write>(filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut CsvWriter<W>) -> Result<Summary, Error> {
+    let pool = thread_pool(filter_command)?;
+    let length = std::fs::metadata(&csv_struct.file).map_err(Error::file(&csv_struct.file))?.len();
+    let chunk_size = (length / pool.current_num_threads() as u64).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+    sized_chunks_to(filter_command, csv_struct, writer, &pool, chunk_size)
+}
+
+/// Filters the files of a glob pattern with [filter_files] and a single file with [filter_chunks]
+/// # Errors
+/// Returns an [Error::InvalidArgument] error with [CsvMetadata::mmap] or when reading stdin, the workers reading their
+/// own byte ranges.
+pub fn filter(filter_command: &Command, csv_struct: &CsvMetadata) -> Result<Summary, Error> {
+    if csv_struct.mmap {
+        return Err(Error::InvalidArgument("--mmap is not supported with --threads".to_string()));
+    }
+    if csv_struct.is_stdin() {
+        return Err(Error::InvalidArgument("Reading stdin is not supported with --threads".to_string()));
+    }
+    match csv_struct.file.contains('*') || csv_struct.source_columns {
+        true => filter_files(filter_command, csv_struct),
+        false => filter_chunks(filter_command, csv_struct),
+    }
+}
+
+/// Smallest byte range of a file filtered by a worker, smaller files are split in fewer ranges than threads
+const MIN_CHUNK_SIZE: u64 = 1 << 20;
+/// Largest byte range of a file filtered by a worker, bounding the memory held by the output of a range
+const MAX_CHUNK_SIZE: u64 = 64 << 20;
+
+/// Builds the pool of [Command::threads] threads, one per core when unset or `0`
+fn thread_pool(filter_command: &Command) -> Result<rayon::ThreadPool, Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(filter_command.threads.unwrap_or(0))
+        .build()
+        .map_err(|e| Error::InvalidArgument(format!("Unable to start the thread pool: {}", e)))
+}
+
+/// Runs `work` on every item on `pool` and writes the outputs to `writer` as they complete, or in the order of the
+/// items when `ordered`
+///
+/// The items are started in order and at most one item per thread is started ahead of the outputs written, so the
+/// outputs held in memory are bounded whatever the number of items or the speed of the writer.
+///
+/// The items after the first error are skipped. When `ordered`, the returned error is the one of the first failing
+/// item, as if the items were processed sequentially.
+fn merge<T, F, W>(pool: &rayon::ThreadPool, items: &[T], ordered: bool, writer: &mut CsvWriter<W>, work: F) -> Result<Summary, Error>
+where
+    T: Sync,
+    F: Fn(usize, &T) -> Result<FileOutput, Error> + Sync,
+    W: Write,
+{
+    let window = pool.current_num_threads();
+    // Index of the first failing item, `usize::MAX` until an item fails
+    let failed = AtomicUsize::new(usize::MAX);
+    // Index of the next item to start
+    let started = AtomicUsize::new(0);
+    // Outputs written so far, the outputs before `written` when `ordered`
+    let written = (Mutex::new(0), Condvar::new());
+    let (sender, receiver) = mpsc::sync_channel::<(usize, Result<FileOutput, Error>)>(window);
+    let (failed_ref, written_ref, work) = (&failed, &written, &work);
+    let mut summary = Summary::default();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || pool.broadcast(|_| {
+            let sender = sender.clone();
+            loop {
+                let index = started.fetch_add(1, Ordering::Relaxed);
+                if index >= items.len() || index > failed_ref.load(Ordering::Relaxed) {
+                    return;
+                }
+                // Waits for the outputs `window` items before to be written
+                let (lock, condvar) = written_ref;
+                let guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+                let guard = condvar
+                    .wait_while(guard, |written| index >= *written + window && index <= failed_ref.load(Ordering::Relaxed))
+                    .unwrap_or_else(PoisonError::into_inner);
+                drop(guard);
+                if index > failed_ref.load(Ordering::Relaxed) {
+                    return;
+                }
+                let output = work(index, &items[index]);
+                if output.is_err() {
+                    failed_ref.fetch_min(index, Ordering::Relaxed);
+                }
+                if sender.send((index, output)).is_err() {
+                    return;
+                }
+            }
+        }));
+
+        let mut write = |(records, item_summary): FileOutput| -> Result<(), Error> {
+            writer.write_raw(&records)?;
+            summary.rows_read += item_summary.rows_read;
+            summary.rows_matched += item_summary.rows_matched;
+            Ok(())
+        };
+        // Wakes up the workers waiting for the outputs before their item
+        let advance = |count: usize| {
+            let (lock, condvar) = written_ref;
+            *lock.lock().unwrap_or_else(PoisonError::into_inner) = count;
+            condvar.notify_all();
+        };
+        // Outputs completed ahead of their turn when `ordered`
+        let mut pending: BTreeMap<usize, Result<FileOutput, Error>> = BTreeMap::new();
+        let mut next = 0;
+        for (index, output) in receiver {
+            let result = match ordered {
+                false => {
+                    next += 1;
+                    output.and_then(&mut write)
+                }
+                true => {
+                    pending.insert(index, output);
+                    let mut result = Ok(());
+                    while let (Ok(()), Some(output)) = (&result, pending.remove(&next)) {
+                        result = output.and_then(&mut write);
+                        next += 1;
+                    }
+                    result
+                }
+            };
+            if result.is_err() {
+                failed.store(0, Ordering::Relaxed);
+                advance(next);
+                return result;
+            }
+            advance(next);
+        }
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Runs the filter of [Command::subcommand] on `buffer`
+fn run_filter<W: Write>(buffer: FileRead, filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut CsvWriter<W>, write_header: bool) -> Result<Summary, Error> {
+    match filter_command.subcommand {
+        Subcommand::Filter(_) => filtering::filter_records(buffer, filter_command, csv_struct, writer, write_header),
+        Subcommand::Anyfilter(_) => filtering::any_filter_records(buffer, filter_command, csv_struct, writer, write_header),
+    }
+}
+
+/// Reads and filters a single file, the header being left out of the output
+fn filter_file(path: &Path, filter_command: &Command, csv_struct: &CsvMetadata, header: Option<&[String]>, output_delimiter: char) -> Result<FileOutput, Error> {
+    let Some(lines) = reader::file_lines(path, csv_struct, header)? else {
+        return Ok((Vec::new(), Summary::default()));
+    };
+    // The filters resolve the columns against the header
+    let lines: reader::Lines = match header {
+        Some(header) => {
+            let record = tokenizer::join_record(&reader::header_columns(csv_struct, header), csv_struct.delimiter);
+            Box::new(std::iter::once(Ok(record)).chain(lines))
+        }
+        None => lines,
+    };
+    let mut writer = CsvWriter::new(Vec::new(), output_delimiter);
+    let summary = run_filter(FileRead::Iterator(lines), filter_command, csv_struct, &mut writer, false)?;
+    Ok((writer.into_inner(), summary))
+}
+
+/// Header record of a file split by [filter_chunks], prepended to every byte range so the filters resolve the columns
+struct Header {
+    /// Raw bytes of the header, line terminators included
+    bytes: Vec<u8>,
+    /// Newlines in the header
+    lines: usize,
+    /// Records in the header, `0` or `1`
+    records: usize,
+}
+
+impl Header {
+    fn read(path: &Path, has_header: bool, delimiter: char) -> Result<Header, Error> {
+        let mut buffer = String::new();
+        if has_header {
+            let file = File::open(path).map_err(Error::file(path))?;
+            tokenizer::read_record(&mut BufReader::new(file), &mut buffer, delimiter)?;
+        }
+        Ok(Header {
+            lines: buffer.matches('\n').count(),
+            records: usize::from(!buffer.is_empty()),
+            bytes: buffer.into_bytes(),
+        })
+    }
+}
+
+/// Byte range of a file starting at a record boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Chunk {
+    start: u64,
+    end: u64,
+    /// Newlines before `start`
+    line: usize,
+    /// Records before `start`, the header included
+    record: usize,
+}
+
+/// Line breaks of a byte range
+///
+/// Whether a line break ends a record depends on the quoted fields before the range, unknown until the ranges before
+/// it are scanned, so the range is scanned from every [QuoteState] and the right run is picked by [split].
+#[derive(Debug)]
+struct Scan {
+    newlines: usize,
+    /// Records of the range scanned from each of [QuoteState::ALL], in order
+    runs: Vec<Run>,
+}
+
+/// Records of a byte range scanned from a given [QuoteState]
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    /// Offset of the first line break ending a record and the newlines before it
+    first: Option<(u64, usize)>,
+    /// Line breaks ending a record
+    record_ends: usize,
+    /// State at the end of the range
+    state: QuoteState,
+    /// Line breaks ending a record of the first run before the one this run shares with it, both runs being the same
+    /// from there on
+    merged: Option<usize>,
+}
+
+impl Scan {
+    /// Run of the range when it starts in `state`
+    fn run(&self, state: QuoteState) -> &Run {
+        let index = QuoteState::ALL.iter().position(|candidate| *candidate == state);
+        &self.runs[index.unwrap_or_else(|| unreachable!("every state is scanned"))]
+    }
+}
+
+/// Scans the bytes of `path` between `start` and `end`, both at the start of a character
+fn scan(path: &Path, start: u64, end: u64, delimiter: char) -> Result<Scan, Error> {
+    let mut file = File::open(path).map_err(Error::file(path))?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = file.take(end - start);
+    let mut buffer = vec![0; 1 << 16];
+    let ends = tokenizer::RecordEnds::new(delimiter);
+    let newlines = Scanner::new(b"\n");
+    let mut scan = Scan {
+        newlines: 0,
+        runs: QuoteState::ALL.iter().map(|state| Run { first: None, record_ends: 0, state: *state, merged: None }).collect(),
+    };
+    // Record ends of the first run in the current block, the other runs merging into it when they share one
+    let mut first_ends = Vec::new();
+    let (mut offset, mut filled) = (0, 0);
+    loop {
+        let read = reader.read(&mut buffer[filled..])?;
+        filled += read;
+        // A character split across two reads is scanned with the second one
+        let length = match read {
+            0 => filled,
+            _ => complete_prefix(&buffer[..filled]),
+        };
+        let block = &buffer[..length];
+        first_ends.clear();
+        let before = scan.runs[0].record_ends;
+        for (index, run) in scan.runs.iter_mut().enumerate() {
+            let mut position = 0;
+            while run.merged.is_none() {
+                let Some(next) = ends.find(block, position, &mut run.state) else {
+                    break;
+                };
+                position = next + 1;
+                let at = offset + next as u64;
+                run.first.get_or_insert_with(|| (at, scan.newlines + newlines.count(&block[..next])));
+                match index {
+                    0 => first_ends.push(at),
+                    _ => run.merged = first_ends.binary_search(&at).ok().map(|i| before + i),
+                }
+                if run.merged.is_none() {
+                    run.record_ends += 1;
+                }
+            }
+        }
+        scan.newlines += newlines.count(block);
+        offset += length as u64;
+        buffer.copy_within(length..filled, 0);
+        filled -= length;
+        if read == 0 {
+            break;
+        }
+    }
+    let (first, others) = scan.runs.split_at_mut(1);
+    for run in others {
+        if let Some(before) = run.merged {
+            run.record_ends += first[0].record_ends - before;
+            run.state = first[0].state;
+        }
+    }
+    Ok(scan)
+}
+
+/// Length of the longest prefix of `bytes` that does not end in the middle of a UTF-8 character
+fn complete_prefix(bytes: &[u8]) -> usize {
+    let Some(lead) = (bytes.len().saturating_sub(4)..bytes.len()).rev().find(|i| bytes[*i] & 0xc0 != 0x80) else {
+        return bytes.len();
+    };
+    let width = match bytes[lead] {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    };
+    match lead + width > bytes.len() {
+        true => lead,
+        false => bytes.len(),
+    }
+}
+
+/// First offset at the start of a character from `offset` in `path`
+fn char_boundary(path: &Path, offset: u64) -> Result<u64, Error> {
+    let mut file = File::open(path).map_err(Error::file(path))?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(3).read_to_end(&mut bytes)?;
+    Ok(offset + bytes.iter().take_while(|byte| **byte & 0xc0 == 0x80).count() as u64)
+}
+
+/// Splits the records of `path` after `header` into ranges of about `chunk_size` bytes
+fn split(pool: &rayon::ThreadPool, path: &Path, header: &Header, chunk_size: u64, delimiter: char) -> Result<Vec<Chunk>, Error> {
+    let length = std::fs::metadata(path)?.len();
+    let data_start = header.bytes.len() as u64;
+    let mut starts = vec![data_start];
+    for start in (data_start..length).step_by(chunk_size as usize).skip(1) {
+        starts.push(char_boundary(path, start)?);
+    }
+    starts.dedup();
+    starts.retain(|start| *start < length);
+    let ranges: Vec<(u64, u64)> = starts.iter().enumerate().map(|(i, start)| (*start, starts.get(i + 1).copied().unwrap_or(length))).collect();
+    let scans = pool.install(|| {
+        ranges.par_iter().map(|&(start, end)| scan(path, start, end, delimiter)).collect::<Result<Vec<Scan>, Error>>()
+    })?;
+
+    let mut chunks = vec![Chunk { start: data_start, end: length, line: header.lines, record: header.records }];
+    let (mut state, mut line, mut record) = (QuoteState::START, header.lines, header.records);
+    for (index, ((start, _), scan)) in ranges.iter().zip(&scans).enumerate() {
+        let run = scan.run(state);
+        // The first range starts right after the header, the others after their first record boundary
+        if let (true, Some((offset, newlines))) = (index > 0, run.first) {
+            let boundary = start + offset + 1;
+            if let Some(last) = chunks.last_mut() {
+                last.end = boundary;
+            }
+            chunks.push(Chunk { start: boundary, end: length, line: line + newlines + 1, record: record + 1 });
+        }
+        state = run.state;
+        line += scan.newlines;
+        record += run.record_ends;
+    }
+    Ok(chunks)
+}
+
+/// Same as [filter_chunks_to] with ranges of about `chunk_size` bytes
+fn sized_chunks_to<W: Write>(filter_command: &Command, csv_struct: &CsvMetadata, writer: &mut CsvWriter<W>, pool: &rayon::ThreadPool, chunk_size: u64) -> Result<Summary, Error> {
+    let path = Path::new(&csv_struct.file);
+    if Compression::from_path(path).is_some() || is_compressed(path)? {
+        return run_filter(reader::csv_reader(csv_struct)?, filter_command, csv_struct, writer, true);
+    }
+    let header = Header::read(path, csv_struct.has_header, csv_struct.delimiter)?;
+    let chunks = split(pool, path, &header, chunk_size, csv_struct.delimiter)?;
+    let output_delimiter = filter_command.output_delimiter.unwrap_or(csv_struct.delimiter);
+    merge(pool, &chunks, true, writer, |index, chunk| {
+        let mut file = File::open(path).map_err(Error::file(path))?;
+        file.seek(SeekFrom::Start(chunk.start))?;
+        let buffer = FileRead::from_reader(Cursor::new(header.bytes.clone()).chain(file.take(chunk.end - chunk.start)));
+        let mut writer = CsvWriter::new(Vec::new(), output_delimiter);
+        // The header is written once, by the first range
+        let summary = run_filter(buffer, filter_command, csv_struct, &mut writer, index == 0)
+            .map_err(|e| locate(e, &header, chunk))?;
+        Ok((writer.into_inner(), summary))
+    })
+}
+
+/// Returns `true` when the data of `path` starts with the magic bytes of a compression format
+fn is_compressed(path: &Path) -> Result<bool, Error> {
+    let mut magic = Vec::new();
+    File::open(path).map_err(Error::file(path))?.take(8).read_to_end(&mut magic)?;
+    Ok(Compression::from_magic(&magic) != Compression::None)
+}
+
+/// Moves the position of an error in a range after the `header` to the position in the file
+fn locate(e: Error, header: &Header, chunk: &Chunk) -> Error {
+    match e {
+        Error::MalformedRecord { line, byte, message } if line > header.lines => Error::MalformedRecord {
+            line: line - header.lines + chunk.line,
+            byte: byte - header.bytes.len() as u64 + chunk.start,
+            message,
+        },
+        Error::TypeConversion(mut e) if e.row > header.records => {
+            e.row = e.row - header.records + chunk.record;
+            Error::TypeConversion(e)
+        }
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes 12 monthly files to a fresh directory, removed when dropped, and returns the metadata of the `*.csv` pattern in it
+    fn partitions() -> (tempfile::TempDir, CsvMetadata) {
+        let dir = tempfile::tempdir().unwrap();
+        for month in 1..=12 {
+            let records: String = (0..50).map(|i| format!("{},{}\n", month * 100 + i, i % 3)).collect();
+            std::fs::write(dir.path().join(format!("{:02}.csv", month)), format!("id,group\n{}", records)).unwrap();
+        }
+        let csv_handler = CsvMetadata {
+            file: dir.path().join("*.csv").to_str().unwrap().to_string(),
+            column_types: vec!["int".to_string()],
+            ..CsvMetadata::default()
+        };
+        (dir, csv_handler)
+    }
+
+    fn command(csv_handler: &CsvMetadata, preserve_order: bool) -> Command {
+        Command {
+            query: vec!["1".to_string()],
+            column: vec!["group".to_string()],
+            threads: Some(4),
+            preserve_order,
+            subcommand: Subcommand::Filter(csv_handler.clone()),
+            ..Command::default()
+        }
+    }
+
+    fn run(filter_command: &Command, csv_handler: &CsvMetadata) -> Result<(String, Summary), Error> {
+        let mut writer = CsvWriter::new(Vec::new(), ',');
+        let summary = filter_files_to(filter_command, csv_handler, &mut writer)?;
+        Ok((String::from_utf8(writer.into_inner()).unwrap(), summary))
+    }
+
+    #[test]
+    fn test_filter_files_matches_sequential() {
+        let (_dir, csv_handler) = partitions();
+        let filter_command = command(&csv_handler, true);
+        let mut writer = CsvWriter::new(Vec::new(), ',');
+        let buffer = reader::glob_reader(&csv_handler).unwrap();
+        let sequential = filtering::filter_to(buffer, &filter_command, &csv_handler, &mut writer).unwrap();
+        let expected = String::from_utf8(writer.into_inner()).unwrap();
+
+        let (ordered, summary) = run(&filter_command, &csv_handler).unwrap();
+        assert_eq!(ordered, expected);
+        assert_eq!(summary, sequential);
+        assert_eq!(summary.rows_read, 600);
+
+        let (unordered, _) = run(&command(&csv_handler, false), &csv_handler).unwrap();
+        assert!(unordered.starts_with("id,group\n"));
+        let mut unordered: Vec<&str> = unordered.lines().collect();
+        let mut expected: Vec<&str> = expected.lines().collect();
+        unordered.sort();
+        expected.sort();
+        assert_eq!(unordered, expected);
+    }
+
+    /// Counts the bytes written through a shared counter
+    struct Counter<'a>(&'a AtomicUsize);
+
+    impl Write for Counter<'_> {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.fetch_add(bytes.len(), Ordering::SeqCst);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_merge_bounds_items_in_flight() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let items: Vec<usize> = (0..200).collect();
+        for ordered in [true, false] {
+            let written = AtomicUsize::new(0);
+            let lead = AtomicUsize::new(0);
+            let mut writer = CsvWriter::new(Counter(&written), ',');
+            merge(&pool, &items, ordered, &mut writer, |index, _| {
+                // Items done out of turn finish late so the ordered outputs pile up without the window
+                if index % 7 == 0 {
+                    std::thread::sleep(std::time::Duration::from_millis(2));
+                }
+                lead.fetch_max(index - written.load(Ordering::SeqCst).min(index), Ordering::SeqCst);
+                Ok((vec![b'x'], Summary { rows_read: 1, rows_matched: 1 }))
+            }).unwrap();
+            assert_eq!(written.load(Ordering::SeqCst), items.len());
+            assert!(lead.load(Ordering::SeqCst) < pool.current_num_threads());
+        }
+    }
+
+    /// Quoted fields with delimiters, escaped quotes and line breaks so most byte ranges start inside a field
+    const QUOTED: &str = "id,note,group\n1,\"a,\n\"\"b\"\"\",1\n2,plain,2\n3,\"\n\n1,x,1\n\",1\n4,\"\"\"\",1\n5,,2\n6,\"end\"\n";
+
+    /// Writes `data` to a temporary file, removed when dropped, and returns its metadata
+    fn single_file(data: &str, has_header: bool) -> (tempfile::NamedTempFile, CsvMetadata) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        let csv_handler = CsvMetadata {
+            file: file.path().to_str().unwrap().to_string(),
+            has_header,
+            column_types: vec![],
+            ..CsvMetadata::default()
+        };
+        (file, csv_handler)
+    }
+
+    /// Output of the sequential filter and of the chunked filter for several range sizes
+    fn filter_both(filter_command: &Command, csv_handler: &CsvMetadata) -> (Result<String, Error>, Vec<Result<String, Error>>) {
+        let mut writer = CsvWriter::new(Vec::new(), ',');
+        let sequential = run_filter(reader::csv_reader(csv_handler).unwrap(), filter_command, csv_handler, &mut writer, true)
+            .map(|_| String::from_utf8(writer.into_inner()).unwrap());
+        let pool = thread_pool(filter_command).unwrap();
+        let chunked = (1..=16).map(|chunk_size| {
+            let mut writer = CsvWriter::new(Vec::new(), ',');
+            sized_chunks_to(filter_command, csv_handler, &mut writer, &pool, chunk_size)
+                .map(|_| String::from_utf8(writer.into_inner()).unwrap())
+        }).collect();
+        (sequential, chunked)
+    }
+
+    #[test]
+    fn test_split_record_boundaries() {
+        let (_file, csv_handler) = single_file(QUOTED, true);
+        let path = Path::new(&csv_handler.file);
+        let header = Header::read(path, true, ',').unwrap();
+        let pool = thread_pool(&command(&csv_handler, true)).unwrap();
+        let chunks = split(&pool, path, &header, 4, ',').unwrap();
+        let starts: Vec<u64> = chunks.iter().map(|chunk| chunk.start).collect();
+        let boundaries: Vec<u64> = vec![14, 29, 39, 54, 63, 68, 76];
+        assert!(starts.iter().all(|start| boundaries.contains(start)));
+        assert_eq!(chunks.first().map(|chunk| chunk.start), Some(14));
+        assert_eq!(chunks.last().map(|chunk| chunk.end), Some(QUOTED.len() as u64));
+        assert!(chunks.windows(2).all(|pair| pair[0].end == pair[1].start));
+        let third = chunks.iter().find(|chunk| chunk.start == 39).unwrap();
+        assert_eq!((third.line, third.record), (4, 3));
+    }
+
+    #[test]
+    fn test_filter_chunks_matches_sequential() {
+        let (_file, csv_handler) = single_file(QUOTED, true);
+        let (sequential, chunked) = filter_both(&command(&csv_handler, false), &csv_handler);
+        let sequential = sequential.unwrap();
+        assert_eq!(sequential.lines().count(), 8);
+        for output in chunked {
+            assert_eq!(output.unwrap(), sequential);
+        }
+
+        let (_file, csv_handler) = single_file(QUOTED, false);
+        let mut filter_command = command(&csv_handler, false);
+        filter_command.column = vec![];
+        filter_command.query = vec!["plain".to_string()];
+        filter_command.subcommand = Subcommand::Anyfilter(csv_handler.clone());
+        let (sequential, chunked) = filter_both(&filter_command, &csv_handler);
+        assert_eq!(sequential.as_deref().unwrap(), "2,plain,2\n");
+        for output in chunked {
+            assert_eq!(output.unwrap(), sequential.as_deref().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_filter_chunks_unquoted_quotes() {
+        let data = "id,note,group\n1,5'10\",1\n2,6ft,2\n3,\"a\"\"\nb \"\"c\",1\n4,x\"y\"z,1\n5,\" é\"\" \",2\n6, \"q\",1\n7,\u{a0}\"r\nr\",1\n8,é\"s,1\n";
+        let (_file, csv_handler) = single_file(data, true);
+        let (sequential, chunked) = filter_both(&command(&csv_handler, false), &csv_handler);
+        let sequential = sequential.unwrap();
+        let expected = "id,note,group\n1,\"5'10\"\"\",1\n3,\"a\"\"\nb \"\"c\",1\n4,\"x\"\"y\"\"z\",1\n6,q,1\n7,\"r\nr\",1\n8,\"é\"\"s\",1\n";
+        assert_eq!(sequential, expected);
+        for output in chunked {
+            assert_eq!(output.unwrap(), sequential);
+        }
+    }
+
+    #[test]
+    fn test_filter_chunks_error_positions() {
+        let data = "id,group\n1,1\n2,1\n3,\"1\n4,1\n";
+        let (_file, csv_handler) = single_file(data, true);
+        let (sequential, chunked) = filter_both(&command(&csv_handler, false), &csv_handler);
+        assert!(matches!(sequential, Err(Error::MalformedRecord { line: 4, byte: 19, .. })));
+        for output in chunked {
+            assert!(matches!(output, Err(Error::MalformedRecord { line: 4, byte: 19, .. })));
+        }
+
+        let data = "id,group\n1,1\n2,\"a\nb\"\n3,x\n";
+        let (_file, mut csv_handler) = single_file(data, true);
+        csv_handler.column_types = vec!["int".to_string(), "int".to_string()];
+        let (sequential, chunked) = filter_both(&command(&csv_handler, false), &csv_handler);
+        let Err(Error::TypeConversion(expected)) = sequential else { panic!("Expected a conversion error") };
+        assert_eq!(expected.row, 3);
+        for output in chunked {
+            assert!(matches!(&output, Err(Error::TypeConversion(e)) if *e == expected), "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn test_filter_rejects_mmap() {
+        let (_file, mut csv_handler) = single_file(QUOTED, true);
+        csv_handler.mmap = true;
+        let result = filter(&command(&csv_handler, false), &csv_handler);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_filter_rejects_stdin() {
+        let csv_handler = CsvMetadata { file: "-".to_string(), ..CsvMetadata::default() };
+        let result = filter(&command(&csv_handler, false), &csv_handler);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_filter_files_error() {
+        let (_dir, csv_handler) = partitions();
+        let dir = csv_handler.file.trim_end_matches("*.csv").to_string();
+        std::fs::write(format!("{}13.csv", dir), "id,grp\n1,1\n").unwrap();
+        let result = run(&command(&csv_handler, true), &csv_handler);
+        assert!(matches!(result, Err(Error::HeaderMismatch { .. })));
+    }
+}
@@ -0,0 +1,166 @@
+//! Column projection of the `--select` and `--drop` options
+//!
+//! A [Projection] picks the output columns with selectors written like the columns of an
+//! [expression](super::expression): a column name, a 1-based position (`$2`), an inclusive range of positions
+//! (`$5-$7`) or a regex matched against the column names (`/^addr_/`). The selected columns are written in the order
+//! of the selectors, each column once, and the dropped columns are then left out. Since the options are comma
+//! separated, a regex cannot contain a comma.
+//!
+//! # Example
+//! ```
+//! use sdk::data::projection::Projection;
+//! let header = ["id", "name", "addr_city", "addr_zip", "notes"];
+//! let select = vec!["name".to_string(), "/^addr_/".to_string(), "$1".to_string()];
+//! let drop = vec!["addr_zip".to_string()];
+//! let projection = Projection::parse(&select, &drop).unwrap().unwrap();
+//! assert_eq!(projection.resolve(Some(&header), header.len()).unwrap(), vec![1, 2, 0]);
+//! ```
+use regex::Regex;
+use std::io::Write;
+use super::super::writer::CsvWriter;
+use crate::Error;
+
+/// Column selector of a [Projection]
+#[derive(Debug, Clone)]
+pub enum Selector {
+    /// Column name, compared to the trimmed header fields
+    Name(String),
+    /// Inclusive range of 0-based positions
+    Range(usize, usize),
+    /// Regex matched against the column names
+    Regex(Regex),
+}
+
+impl Selector {
+    /// Parses a selector, see the [module](self) documentation for the syntax
+    /// # Errors
+    /// Returns an [Error::InvalidArgument] error if a position is not a positive number or a regex is invalid.
+    pub fn parse(source: &str) -> Result<Selector, Error> {
+        let source = source.trim();
+        let invalid = |message: String| Error::InvalidArgument(format!("Invalid column selector `{}`: {}", source, message));
+        if let Some(pattern) = source.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+            return Regex::new(pattern).map(Selector::Regex).map_err(|e| invalid(e.to_string()));
+        }
+        let Some(range) = source.strip_prefix('$') else {
+            return Ok(Selector::Name(source.to_string()));
+        };
+        let position = |position: &str| match position.trim_start_matches('$').parse::<usize>() {
+            Ok(position) if position > 0 => Ok(position - 1),
+            _ => Err(invalid("expected 1-based column positions, e.g. `$2` or `$5-$7`".to_string())),
+        };
+        match range.split_once('-') {
+            Some((first, last)) => match (position(first)?, position(last)?) {
+                (first, last) if first <= last => Ok(Selector::Range(first, last)),
+                _ => Err(invalid("the range is reversed".to_string())),
+            },
+            None => position(range).map(|position| Selector::Range(position, position)),
+        }
+    }
+
+    /// Positions of the columns matched by the selector, in the order of the header
+    ///
+    /// `width` is the number of columns, the header length or, without header, the length of the first record.
+    pub(crate) fn positions<S: AsRef<str>>(&self, header: Option<&[S]>, width: usize) -> Result<Vec<usize>, Error> {
+        let names = || header.ok_or_else(|| Error::InvalidArgument("Selecting columns by name requires a header".to_string()));
+        match self {
+            Selector::Name(name) => match names()?.iter().position(|column| column.as_ref().trim() == name) {
+                Some(position) => Ok(vec![position]),
+                None => Err(Error::MissingColumn(name.clone())),
+            },
+            Selector::Range(_, last) if *last >= width => Err(Error::MissingColumn(format!("${}", last + 1))),
+            Selector::Range(first, last) => Ok((*first..=*last).collect()),
+            Selector::Regex(regex) => Ok(names()?
+                .iter()
+                .enumerate()
+                .filter(|(_, column)| regex.is_match(column.as_ref().trim()))
+                .map(|(position, _)| position)
+                .collect()),
+        }
+    }
+}
+
+/// Output columns of a command, see [Command::select](super::manipulation::Command::select) and
+/// [Command::drop](super::manipulation::Command::drop)
+#[derive(Debug, Clone)]
+pub struct Projection {
+    select: Vec<Selector>,
+    drop: Vec<Selector>,
+}
+
+impl Projection {
+    /// Parses the selectors of the selected and dropped columns, `None` when both are empty so every column is written
+    /// # Errors
+    /// Same as [Selector::parse].
+    pub fn parse(select: &[String], drop: &[String]) -> Result<Option<Projection>, Error> {
+        if select.is_empty() && drop.is_empty() {
+            return Ok(None);
+        }
+        let parse = |sources: &[String]| sources.iter().map(|source| Selector::parse(source)).collect::<Result<Vec<Selector>, Error>>();
+        Ok(Some(Projection { select: parse(select)?, drop: parse(drop)? }))
+    }
+
+    /// Positions of the output columns in a record of `width` columns
+    /// # Errors
+    /// Returns an [Error::MissingColumn] error if a name is not found in the header or a position is past the last
+    /// column, and an [Error::InvalidArgument] error if a name or regex is used without header.
+    pub fn resolve<S: AsRef<str>>(&self, header: Option<&[S]>, width: usize) -> Result<Vec<usize>, Error> {
+        let mut columns: Vec<usize> = match self.select.is_empty() {
+            true => (0..width).collect(),
+            false => Vec::new(),
+        };
+        for selector in &self.select {
+            for position in selector.positions(header, width)? {
+                if !columns.contains(&position) {
+                    columns.push(position);
+                }
+            }
+        }
+        for selector in &self.drop {
+            let dropped = selector.positions(header, width)?;
+            columns.retain(|position| !dropped.contains(position));
+        }
+        Ok(columns)
+    }
+}
+
+/// Writes the `columns` of a record, every field when `None`, fields missing from a short record being left empty
+pub(crate) fn write_projected<W: Write, S: AsRef<str>>(writer: &mut CsvWriter<W>, fields: &[S], columns: Option<&[usize]>) -> Result<(), Error> {
+    match columns {
+        Some(columns) => {
+            let projected: Vec<&str> = columns.iter().map(|i| fields.get(*i).map_or("", |field| field.as_ref())).collect();
+            writer.write_record(&projected)
+        }
+        None => writer.write_record(fields),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: [&str; 6] = ["id", "name", "addr_city", "addr_zip", "notes", "total"];
+
+    fn resolve(select: &[&str], drop: &[&str], header: Option<&[&str]>) -> Result<Vec<usize>, Error> {
+        let strings = |sources: &[&str]| sources.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        Projection::parse(&strings(select), &strings(drop))?.unwrap().resolve(header, HEADER.len())
+    }
+
+    #[test]
+    fn test_selectors() {
+        assert_eq!(resolve(&["total", "$1", "/^addr_/"], &[], Some(&HEADER)).unwrap(), vec![5, 0, 2, 3]);
+        assert_eq!(resolve(&["$2-$4", "name"], &[], Some(&HEADER)).unwrap(), vec![1, 2, 3]);
+        assert_eq!(resolve(&[], &["notes", "$1"], Some(&HEADER)).unwrap(), vec![1, 2, 3, 5]);
+        assert_eq!(resolve(&["$5-6", "$1"], &["$6"], None).unwrap(), vec![4, 0]);
+        assert!(Projection::parse(&[], &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_selector_errors() {
+        assert!(matches!(resolve(&["missing"], &[], Some(&HEADER)), Err(Error::MissingColumn(name)) if name == "missing"));
+        assert!(matches!(resolve(&["$7"], &[], Some(&HEADER)), Err(Error::MissingColumn(name)) if name == "$7"));
+        assert!(matches!(resolve(&["name"], &[], None), Err(Error::InvalidArgument(_))));
+        for source in ["$0", "$x", "$4-$2", "/(/"] {
+            assert!(matches!(Selector::parse(source), Err(Error::InvalidArgument(_))), "{}", source);
+        }
+    }
+}
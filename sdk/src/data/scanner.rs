@@ -0,0 +1,238 @@
+//! Vectorized search of the structural bytes of csv data: delimiters, quotes and line breaks
+//!
+//! A [Scanner] looks for up to three needle bytes 32 bytes at a time with AVX2 or 16 bytes at a time with SSE2, the
+//! instruction set being detected at runtime on x86_64. Other targets fall back to a portable byte by byte search.
+//! The [tokenizer](super::tokenizer) jumps from one structural byte to the next with it instead of inspecting every
+//! character of a record.
+//!
+//! # Example
+//! ```
+//! use sdk::data::scanner::Scanner;
+//! let scanner = Scanner::new(&[b',', b'"']);
+//! assert_eq!(scanner.find(b"abc,\"d\"", 0), Some(3));
+//! assert_eq!(scanner.find(b"abc,\"d\"", 4), Some(4));
+//! assert_eq!(scanner.count(b"abc,\"d\""), 3);
+//! ```
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Instruction set used by a [Scanner]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    /// Byte by byte search, available on every target
+    Portable,
+    /// 16 bytes at a time, x86_64 only
+    Sse2,
+    /// 32 bytes at a time, x86_64 only
+    Avx2,
+}
+
+impl Kernel {
+    /// Fastest kernel supported by the cpu
+    pub fn detect() -> Kernel {
+        [Kernel::Avx2, Kernel::Sse2].into_iter().find(Kernel::is_supported).unwrap_or(Kernel::Portable)
+    }
+
+    /// Returns `true` when the cpu supports the kernel
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Kernel::Portable => true,
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => false,
+        }
+    }
+
+    /// Name of the kernel in lower case, e.g. `avx2`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::Portable => "portable",
+            Kernel::Sse2 => "sse2",
+            Kernel::Avx2 => "avx2",
+        }
+    }
+}
+
+/// Searches bytes for up to three needles
+#[derive(Debug, Clone, Copy)]
+pub struct Scanner {
+    needles: [u8; 3],
+    kernel: Kernel,
+}
+
+impl Scanner {
+    /// Searches for `needles` with the fastest kernel of the cpu, see [Kernel::detect]
+    /// # Panics
+    /// Panics if `needles` is empty or has more than three bytes.
+    pub fn new(needles: &[u8]) -> Scanner {
+        Scanner::with_kernel(needles, Kernel::detect()).unwrap_or_else(|| unreachable!("the detected kernel is supported"))
+    }
+
+    /// Searches for `needles` with `kernel`, `None` when the cpu does not support it
+    /// # Panics
+    /// Panics if `needles` is empty or has more than three bytes.
+    pub fn with_kernel(needles: &[u8], kernel: Kernel) -> Option<Scanner> {
+        assert!((1..=3).contains(&needles.len()), "a scanner searches for 1 to 3 bytes");
+        // Missing needles repeat the first one so every kernel compares three bytes
+        let needles = [needles[0], needles[needles.len().min(2) - 1], needles[needles.len() - 1]];
+        kernel.is_supported().then_some(Scanner { needles, kernel })
+    }
+
+    /// Kernel used by the scanner
+    pub fn kernel(&self) -> Kernel {
+        self.kernel
+    }
+
+    /// Position of the first needle in `haystack` at or after `from`
+    pub fn find(&self, haystack: &[u8], from: usize) -> Option<usize> {
+        let rest = haystack.get(from..)?;
+        let position = match self.kernel {
+            Kernel::Portable => find_portable(&self.needles, rest),
+            // SAFETY: the scanner is only built with a kernel supported by the cpu
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => unsafe { find_sse2(&self.needles, rest) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { find_avx2(&self.needles, rest) },
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => find_portable(&self.needles, rest),
+        };
+        position.map(|position| from + position)
+    }
+
+    /// Number of needles in `haystack`
+    pub fn count(&self, haystack: &[u8]) -> usize {
+        match self.kernel {
+            Kernel::Portable => count_portable(&self.needles, haystack),
+            // SAFETY: the scanner is only built with a kernel supported by the cpu
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => unsafe { count_sse2(&self.needles, haystack) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { count_avx2(&self.needles, haystack) },
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => count_portable(&self.needles, haystack),
+        }
+    }
+}
+
+fn is_needle(needles: &[u8; 3], byte: u8) -> bool {
+    byte == needles[0] || byte == needles[1] || byte == needles[2]
+}
+
+fn find_portable(needles: &[u8; 3], haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|byte| is_needle(needles, *byte))
+}
+
+fn count_portable(needles: &[u8; 3], haystack: &[u8]) -> usize {
+    haystack.iter().filter(|byte| is_needle(needles, **byte)).count()
+}
+
+/// Bit mask of the needles in the 16 bytes at `pointer`
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn mask_sse2(needles: &[__m128i; 3], pointer: *const u8) -> u32 {
+    let chunk = _mm_loadu_si128(pointer as *const __m128i);
+    let matches = _mm_or_si128(
+        _mm_or_si128(_mm_cmpeq_epi8(chunk, needles[0]), _mm_cmpeq_epi8(chunk, needles[1])),
+        _mm_cmpeq_epi8(chunk, needles[2]),
+    );
+    _mm_movemask_epi8(matches) as u32
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn find_sse2(needles: &[u8; 3], haystack: &[u8]) -> Option<usize> {
+    let vectors = needles.map(|needle| _mm_set1_epi8(needle as i8));
+    let mut offset = 0;
+    while offset + 16 <= haystack.len() {
+        let mask = mask_sse2(&vectors, haystack.as_ptr().add(offset));
+        if mask != 0 {
+            return Some(offset + mask.trailing_zeros() as usize);
+        }
+        offset += 16;
+    }
+    find_portable(needles, &haystack[offset..]).map(|position| offset + position)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn count_sse2(needles: &[u8; 3], haystack: &[u8]) -> usize {
+    let vectors = needles.map(|needle| _mm_set1_epi8(needle as i8));
+    let (mut offset, mut count) = (0, 0);
+    while offset + 16 <= haystack.len() {
+        count += mask_sse2(&vectors, haystack.as_ptr().add(offset)).count_ones() as usize;
+        offset += 16;
+    }
+    count + count_portable(needles, &haystack[offset..])
+}
+
+/// Bit mask of the needles in the 32 bytes at `pointer`
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn mask_avx2(needles: &[__m256i; 3], pointer: *const u8) -> u32 {
+    let chunk = _mm256_loadu_si256(pointer as *const __m256i);
+    let matches = _mm256_or_si256(
+        _mm256_or_si256(_mm256_cmpeq_epi8(chunk, needles[0]), _mm256_cmpeq_epi8(chunk, needles[1])),
+        _mm256_cmpeq_epi8(chunk, needles[2]),
+    );
+    _mm256_movemask_epi8(matches) as u32
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn find_avx2(needles: &[u8; 3], haystack: &[u8]) -> Option<usize> {
+    let vectors = needles.map(|needle| _mm256_set1_epi8(needle as i8));
+    let mut offset = 0;
+    while offset + 32 <= haystack.len() {
+        let mask = mask_avx2(&vectors, haystack.as_ptr().add(offset));
+        if mask != 0 {
+            return Some(offset + mask.trailing_zeros() as usize);
+        }
+        offset += 32;
+    }
+    find_sse2(needles, &haystack[offset..]).map(|position| offset + position)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn count_avx2(needles: &[u8; 3], haystack: &[u8]) -> usize {
+    let vectors = needles.map(|needle| _mm256_set1_epi8(needle as i8));
+    let (mut offset, mut count) = (0, 0);
+    while offset + 32 <= haystack.len() {
+        count += mask_avx2(&vectors, haystack.as_ptr().add(offset)).count_ones() as usize;
+        offset += 32;
+    }
+    count + count_sse2(needles, &haystack[offset..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernels_agree() {
+        // Needles at every offset of the vectors, including the tails shorter than a vector
+        let data: Vec<u8> = (0..517u32).map(|i| match i % 37 {
+            0 => b',',
+            5 | 31 => b'"',
+            17 => b'\n',
+            _ => b'a' + (i % 26) as u8,
+        }).collect();
+        let kernels = [Kernel::Portable, Kernel::Sse2, Kernel::Avx2];
+        for needles in [&b","[..], &b",\""[..], &b",\"\n"[..]] {
+            let portable = Scanner::with_kernel(needles, Kernel::Portable).unwrap();
+            for scanner in kernels.iter().filter_map(|kernel| Scanner::with_kernel(needles, *kernel)) {
+                for start in 0..70 {
+                    let haystack = &data[start..];
+                    assert_eq!(scanner.count(haystack), portable.count(haystack), "{:?}", scanner.kernel());
+                    for from in 0..=haystack.len() {
+                        assert_eq!(scanner.find(haystack, from), portable.find(haystack, from), "{:?}", scanner.kernel());
+                    }
+                }
+            }
+        }
+        assert_eq!(Scanner::new(b",").find(b"abc", 5), None);
+    }
+}
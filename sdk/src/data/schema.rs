@@ -0,0 +1,293 @@
+//! Typed columns driven by [CsvMetadata::column_types](crate::reader::CsvMetadata::column_types)
+//!
+//! The `--column-types` list is positional: the n-th entry is the type of the n-th column and columns past the end of
+//! the list are read as strings. Fields are parsed into a [Value], empty fields of non-string columns become [Value::Null].
+//!
+//! # Example
+//! ```
+//! use sdk::data::schema::{Schema, Value};
+//! let schema = Schema::from_column_types(&["int".to_string(), "float".to_string()]).unwrap();
+//! let values = schema.parse_record(2, &["42".to_string(), "".to_string(), "text".to_string()]).unwrap();
+//! assert_eq!(values, vec![Value::Int(42), Value::Null, Value::String("text".to_string())]);
+//! ```
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use crate::Error;
+
+const DATE_FORMATS: [&str; 2] = ["%Y-%m-%d", "%Y/%m/%d"];
+const DATETIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Supported column types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Date,
+    Datetime,
+    Decimal,
+}
+
+impl ColumnType {
+    /// Name of the type as accepted by `--column-types`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::String => "string",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+            ColumnType::Datetime => "datetime",
+            ColumnType::Decimal => "decimal",
+        }
+    }
+
+    /// Parses a raw field into a [Value] of this type. Surrounding whitespace is ignored.
+    ///
+    /// Returns `None` if the field cannot be represented by the type.
+    pub fn parse(&self, field: &str) -> Option<Value> {
+        let trimmed = field.trim();
+        if trimmed.is_empty() && *self != ColumnType::String {
+            return Some(Value::Null);
+        }
+        match self {
+            ColumnType::String => Some(Value::String(trimmed.to_string())),
+            ColumnType::Int => trimmed.parse().ok().map(Value::Int),
+            ColumnType::Float => trimmed.parse().ok().map(Value::Float),
+            ColumnType::Bool => parse_bool(trimmed).map(Value::Bool),
+            ColumnType::Date => DATE_FORMATS
+                .iter()
+                .find_map(|format| NaiveDate::parse_from_str(trimmed, format).ok())
+                .map(Value::Date),
+            ColumnType::Datetime => DATETIME_FORMATS
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(trimmed, format).ok())
+                .map(Value::Datetime),
+            ColumnType::Decimal => Decimal::from_str(trimmed).ok().map(Value::Decimal),
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "string" | "str" | "text" => Ok(ColumnType::String),
+            "int" | "integer" | "i64" => Ok(ColumnType::Int),
+            "float" | "double" | "f64" => Ok(ColumnType::Float),
+            "bool" | "boolean" => Ok(ColumnType::Bool),
+            "date" => Ok(ColumnType::Date),
+            "datetime" | "timestamp" => Ok(ColumnType::Datetime),
+            "decimal" => Ok(ColumnType::Decimal),
+            other => Err(format!("Unknown column type `{}`", other)),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+fn parse_bool(field: &str) -> Option<bool> {
+    match field.to_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "1" => Some(true),
+        "false" | "f" | "no" | "n" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// A typed field value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Date(NaiveDate),
+    Datetime(NaiveDateTime),
+    Decimal(Decimal),
+}
+
+impl Value {
+    /// Compares two values, returning `None` when they are not comparable (different types or a null).
+    ///
+    /// Numeric values are compared across `Int`, `Float` and `Decimal`.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+            (Value::Datetime(a), Value::Datetime(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Datetime(b)) => a.and_hms_opt(0, 0, 0).map(|a| a.cmp(b)),
+            (Value::Datetime(a), Value::Date(b)) => b.and_hms_opt(0, 0, 0).map(|b| a.cmp(&b)),
+            (Value::Decimal(a), Value::Decimal(b)) => Some(a.cmp(b)),
+            (Value::Decimal(a), Value::Int(b)) => Some(a.cmp(&Decimal::from(*b))),
+            (Value::Int(a), Value::Decimal(b)) => Some(Decimal::from(*a).cmp(b)),
+            _ => match (self.as_f64(), other.as_f64()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => None,
+            },
+        }
+    }
+
+    /// Equality used by the filters: nulls match each other, other values match when they compare equal
+    pub fn matches(&self, other: &Value) -> bool {
+        self == other || self.compare(other) == Some(Ordering::Equal)
+    }
+
+    /// Numeric view of the value, `None` for non-numeric values
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(v) => Some(*v as f64),
+            Value::Float(v) => Some(*v),
+            Value::Decimal(v) => v.to_string().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Type of the value, `None` for [Value::Null]
+    pub fn column_type(&self) -> Option<ColumnType> {
+        match self {
+            Value::Null => None,
+            Value::String(_) => Some(ColumnType::String),
+            Value::Int(_) => Some(ColumnType::Int),
+            Value::Float(_) => Some(ColumnType::Float),
+            Value::Bool(_) => Some(ColumnType::Bool),
+            Value::Date(_) => Some(ColumnType::Date),
+            Value::Datetime(_) => Some(ColumnType::Datetime),
+            Value::Decimal(_) => Some(ColumnType::Decimal),
+        }
+    }
+
+    /// Returns `true` for [Value::Null]
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::String(v) => f.write_str(v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Date(v) => write!(f, "{}", v.format("%Y-%m-%d")),
+            Value::Datetime(v) => write!(f, "{}", v.format("%Y-%m-%dT%H:%M:%S%.f")),
+            Value::Decimal(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Error raised when a field cannot be converted to its column type
+///
+/// `row` is the 1-based record number in the input (the header being record 1) and `column` the 0-based column index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub row: usize,
+    pub column: usize,
+    pub value: String,
+    pub column_type: ColumnType,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to convert `{}` to {} at row {}, column {}",
+            self.value, self.column_type, self.row, self.column
+        )
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Per-column types of a csv file
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Schema {
+    pub columns: Vec<ColumnType>,
+}
+
+impl Schema {
+    /// Builds a schema from the `--column-types` list
+    /// # Errors
+    /// Returns an [Error::InvalidArgument] error for unknown type names.
+    pub fn from_column_types(column_types: &[String]) -> Result<Schema, Error> {
+        let columns = column_types
+            .iter()
+            .map(|name| name.parse().map_err(Error::InvalidArgument))
+            .collect::<Result<Vec<ColumnType>, Error>>()?;
+        Ok(Schema { columns })
+    }
+
+    /// Type of the column at `index`, columns without a declared type are strings
+    pub fn column_type(&self, index: usize) -> ColumnType {
+        self.columns.get(index).copied().unwrap_or(ColumnType::String)
+    }
+
+    /// Parses the field of column `column` located at record `row`
+    pub fn parse_field(&self, row: usize, column: usize, field: &str) -> Result<Value, ConversionError> {
+        let column_type = self.column_type(column);
+        column_type.parse(field).ok_or_else(|| ConversionError {
+            row,
+            column,
+            value: field.to_string(),
+            column_type,
+        })
+    }
+
+    /// Parses every field of the record located at `row`
+    pub fn parse_record<S: AsRef<str>>(&self, row: usize, fields: &[S]) -> Result<Vec<Value>, ConversionError> {
+        fields
+            .iter()
+            .enumerate()
+            .map(|(column, field)| self.parse_field(row, column, field.as_ref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_types() {
+        assert_eq!(ColumnType::Bool.parse("Yes"), Some(Value::Bool(true)));
+        assert_eq!(ColumnType::Int.parse(" 7 "), Some(Value::Int(7)));
+        assert_eq!(ColumnType::Int.parse("7.5"), None);
+        assert_eq!(ColumnType::Date.parse("2024-02-29"), NaiveDate::from_ymd_opt(2024, 2, 29).map(Value::Date));
+        assert!(matches!(ColumnType::Datetime.parse("2024-02-29 10:30:00"), Some(Value::Datetime(_))));
+        assert_eq!(ColumnType::Decimal.parse("1.10"), Some(Value::Decimal(Decimal::new(110, 2))));
+        assert_eq!(ColumnType::Float.parse(""), Some(Value::Null));
+    }
+
+    #[test]
+    fn test_compare_numeric() {
+        assert_eq!(Value::Int(2).compare(&Value::Float(2.0)), Some(Ordering::Equal));
+        assert_eq!(Value::Decimal(Decimal::new(15, 1)).compare(&Value::Int(1)), Some(Ordering::Greater));
+        assert_eq!(Value::Null.compare(&Value::Int(1)), None);
+    }
+
+    #[test]
+    fn test_conversion_error_position() {
+        let schema = Schema::from_column_types(&["string".to_string(), "int".to_string()]).unwrap();
+        let error = schema.parse_record(3, &["a", "b"]).unwrap_err();
+        assert_eq!((error.row, error.column), (3, 1));
+        assert!(Schema::from_column_types(&["uuid".to_string()]).is_err());
+    }
+}
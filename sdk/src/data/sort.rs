@@ -0,0 +1,397 @@
+//! External merge sort of the records of a csv file, the `sort` subcommand
+//!
+//! The records are sorted by one or more columns, each ascending or descending, the fields being compared as typed
+//! values following [CsvMetadata::column_types]. The sort is stable: records with equal keys keep the order of the
+//! input. Null fields, i.e. empty or missing fields, are placed first or last whatever the direction of the column.
+//!
+//! Records are buffered up to [SortCommand::memory_limit]. When the buffer is full it is sorted and spilled to a
+//! temporary file as a sorted run, the runs being merged at the end with a k-way merge over a [BinaryHeap]. Files read
+//! through a glob pattern are sorted together, see [loader](crate::loader).
+//!
+//! # Example
+//! ```
+//! # use sdk::data::manipulation::FileRead;
+//! # use sdk::data::sort::{self, NullOrder, SortCommand};
+//! # use sdk::reader::CsvMetadata;
+//! # use sdk::writer::CsvWriter;
+//! let command = SortCommand {
+//!     by: vec!["state".to_string(), "age:desc".to_string()],
+//!     nulls: NullOrder::Last,
+//!     memory_limit: 1 << 20,
+//!     temp_dir: None,
+//!     output_path: None,
+//!     output_delimiter: None,
+//!     compress: None,
+//!     csv: CsvMetadata {
+//!         file: "in-memory.csv".to_string(),
+//!         column_types: vec!["string".to_string(), "int".to_string()],
+//!         ..CsvMetadata::default()
+//!     },
+//! };
+//! let buffer = FileRead::from_reader(std::io::Cursor::new("state,age\nNY,30\nCA,25\nNY,41\nCA,\n"));
+//! let mut writer = CsvWriter::new(Vec::new(), ',');
+//! assert_eq!(sort::sort_to(buffer, &command, &mut writer).unwrap(), 4);
+//! assert_eq!(writer.into_inner(), b"state,age\nCA,25\nCA,\nNY,41\nNY,30\n");
+//! ```
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use clap::{Parser, ValueEnum};
+use super::manipulation::FileRead;
+use super::projection::Selector;
+use super::schema::{Schema, Value};
+use super::super::compression::Compression;
+use super::super::reader::CsvMetadata;
+use super::super::writer::CsvWriter;
+use crate::Error;
+
+/// Number of runs merged at once, bounding the number of open temporary files
+const MAX_MERGED_RUNS: usize = 64;
+
+/// Command struct to hold the sort arguments
+#[derive(Parser, Debug)]
+pub struct SortCommand {
+    #[clap(long, value_delimiter = ',', required = true, help = "Columns to sort by comma separated, names or positions `$2`, suffixed by `:desc` for a descending order, e.g. `state,age:desc`")]
+    pub by: Vec<String>,
+    #[clap(long, value_enum, default_value_t = NullOrder::Last, help = "Whether empty and missing fields are placed first or last")]
+    pub nulls: NullOrder,
+    #[clap(long, value_parser = parse_size, default_value = "256M", help = "Records buffered in memory before sorted runs are spilled to temporary files, in bytes with an optional K, M or G suffix")]
+    pub memory_limit: usize,
+    #[clap(long, help = "Directory of the temporary files, defaults to the system temporary directory")]
+    pub temp_dir: Option<PathBuf>,
+    #[clap(long, help = "Output path for the sorted data, defaults to stdout")]
+    pub output_path: Option<String>,
+    #[clap(long, value_parser = crate::reader::parse_delimiter, help = "Delimiter of the output data, defaults to the input delimiter")]
+    pub output_delimiter: Option<char>,
+    #[clap(long, value_enum, help = "Compression of the output data, defaults to the output path extension, e.g. `*.csv.gz`")]
+    pub compress: Option<Compression>,
+    #[clap(flatten)]
+    pub csv: CsvMetadata,
+}
+
+/// Placement of the null fields
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullOrder {
+    First,
+    Last,
+}
+
+/// Parses a size in bytes with an optional `K`, `M` or `G` binary suffix, e.g. `512M`
+pub fn parse_size(arg: &str) -> Result<usize, String> {
+    let arg = arg.trim();
+    let (digits, shift) = match arg.to_ascii_uppercase().chars().last() {
+        Some('K') => (&arg[..arg.len() - 1], 10),
+        Some('M') => (&arg[..arg.len() - 1], 20),
+        Some('G') => (&arg[..arg.len() - 1], 30),
+        _ => (arg, 0),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|size| size.checked_mul(1 << shift))
+        .ok_or_else(|| format!("Invalid size `{}`, expected a number of bytes with an optional K, M or G suffix", arg))
+}
+
+/// Sort keys resolved against the header
+struct Order {
+    /// Column position and descending flag of every key
+    columns: Vec<(usize, bool)>,
+    nulls: NullOrder,
+    schema: Schema,
+}
+
+impl Order {
+    fn new(command: &SortCommand, header: Option<&[String]>, width: usize) -> Result<Order, Error> {
+        let mut columns = Vec::new();
+        for source in &command.by {
+            let (source, descending) = match source.trim().rsplit_once(':') {
+                Some((source, "desc")) => (source, true),
+                Some((source, "asc")) => (source, false),
+                _ => (source.as_str(), false),
+            };
+            for position in Selector::parse(source)?.positions(header, width)? {
+                columns.push((position, descending));
+            }
+        }
+        Ok(Order { columns, nulls: command.nulls, schema: Schema::from_column_types(&command.csv.column_types)? })
+    }
+
+    /// Typed keys of the record at the 1-based `row`, missing fields being nulls
+    fn keys(&self, record: &[String], row: usize) -> Result<Vec<Value>, Error> {
+        self.columns
+            .iter()
+            .map(|(position, _)| match record.get(*position) {
+                Some(field) => Ok(self.schema.parse_field(row, *position, field)?),
+                None => Ok(Value::Null),
+            })
+            .collect()
+    }
+
+    fn compare(&self, left: &[Value], right: &[Value]) -> Ordering {
+        let is_null = |value: &Value| value.is_null() || *value == Value::String(String::new());
+        for ((left, right), (_, descending)) in left.iter().zip(right).zip(&self.columns) {
+            let ordering = match (is_null(left), is_null(right)) {
+                (true, true) => Ordering::Equal,
+                (true, false) if self.nulls == NullOrder::First => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (false, true) if self.nulls == NullOrder::First => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // Values of a typed column are always comparable, except NaN floats
+                (false, false) if *descending => right.compare(left).unwrap_or(Ordering::Equal),
+                (false, false) => left.compare(right).unwrap_or(Ordering::Equal),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// Buffered record with its keys
+struct Entry {
+    keys: Vec<Value>,
+    record: Vec<String>,
+}
+
+impl Entry {
+    /// Approximate memory used by the entry
+    fn size(&self) -> usize {
+        let strings = |record: &[String]| record.iter().map(|field| field.len() + size_of::<String>()).sum::<usize>();
+        size_of::<Entry>() + strings(&self.record) + self.keys.len() * size_of::<Value>()
+    }
+}
+
+/// Next record of a sorted run during the merge, the heap pops the smallest key of the earliest run first
+struct Head<'a> {
+    entry: Entry,
+    run: usize,
+    order: &'a Order,
+}
+
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed since the heap is a max-heap, ties are broken by the run so the merge is stable
+        self.order.compare(&other.entry.keys, &self.entry.keys).then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head<'_> {}
+
+/// Sorts the records of `buffer` and streams them to [SortCommand::output_path], stdout by default
+/// # Errors
+/// Same as [sort_to].
+pub fn sort(buffer: FileRead, command: &SortCommand) -> Result<usize, Error> {
+    let output_delimiter = command.output_delimiter.unwrap_or(command.csv.delimiter);
+    let mut writer = CsvWriter::open(command.output_path.as_deref(), output_delimiter, command.compress)?;
+    let rows = sort_to(buffer, command, &mut writer)?;
+    writer.finish()?;
+    Ok(rows)
+}
+
+/// Same as [sort] but streams the results to `writer` instead of [SortCommand::output_path], returning the number of
+/// sorted records
+/// # Errors
+/// Returns an [Error::MissingColumn] error if a sort column is not in the header, an [Error::InvalidArgument] error if
+/// a sort column is not valid, an [Error::TypeConversion] error if a field cannot be converted to its column type and
+/// an [Error::Io] error if a temporary file cannot be written.
+pub fn sort_to<W: Write>(buffer: FileRead, command: &SortCommand, writer: &mut CsvWriter<W>) -> Result<usize, Error> {
+    let delimiter = command.csv.delimiter;
+    let mut order: Option<Order> = None;
+    let mut entries: Vec<Entry> = Vec::new();
+    let (mut size, mut rows) = (0, 0);
+    let mut runs: Vec<(usize, File)> = Vec::new();
+    for (index, record) in buffer.records(delimiter).enumerate() {
+        let record = record?;
+        if index == 0 && command.csv.has_header {
+            order = Some(Order::new(command, Some(&record), record.len())?);
+            writer.write_record(&record)?;
+            continue;
+        }
+        let order = match &mut order {
+            Some(order) => order,
+            None => order.insert(Order::new(command, None, record.len())?),
+        };
+        let entry = Entry { keys: order.keys(&record, index + 1)?, record };
+        size += entry.size();
+        rows += 1;
+        entries.push(entry);
+        if size > command.memory_limit {
+            let run = spill(&mut entries, order, command)?;
+            push_run(&mut runs, run, order, command)?;
+            size = 0;
+        }
+    }
+    let Some(order) = order else {
+        return Ok(0);
+    };
+    if runs.is_empty() {
+        entries.sort_by(|left, right| order.compare(&left.keys, &right.keys));
+        for entry in &entries {
+            writer.write_record(&entry.record)?;
+        }
+    } else {
+        if !entries.is_empty() {
+            runs.push((0, spill(&mut entries, &order, command)?));
+        }
+        merge(runs.into_iter().map(|(_, run)| run).collect(), &order, delimiter, writer)?;
+    }
+    writer.flush()?;
+    Ok(rows)
+}
+
+fn temp_file(command: &SortCommand) -> Result<File, Error> {
+    Ok(match &command.temp_dir {
+        Some(dir) => tempfile::tempfile_in(dir)?,
+        None => tempfile::tempfile()?,
+    })
+}
+
+/// Flushes a run and moves back to its start for the merge
+fn rewind(run: CsvWriter<BufWriter<File>>) -> Result<File, Error> {
+    let mut file = run.into_inner().into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Sorts the buffered entries and writes them to a temporary file, emptying the buffer
+fn spill(entries: &mut Vec<Entry>, order: &Order, command: &SortCommand) -> Result<File, Error> {
+    entries.sort_by(|left, right| order.compare(&left.keys, &right.keys));
+    let mut run = CsvWriter::new(BufWriter::new(temp_file(command)?), command.csv.delimiter);
+    for entry in entries.drain(..) {
+        run.write_record(&entry.record)?;
+    }
+    rewind(run)
+}
+
+/// Adds a spilled run to `runs`, the runs being tagged with the number of merges they went through
+///
+/// Once [MAX_MERGED_RUNS] runs of the same level end the list they are merged into a single run of the next level,
+/// so a few hundred files at most are open whatever the input size. Only consecutive runs are merged together, so
+/// ties keep the order of the input.
+fn push_run(runs: &mut Vec<(usize, File)>, run: File, order: &Order, command: &SortCommand) -> Result<(), Error> {
+    runs.push((0, run));
+    loop {
+        let level = runs[runs.len() - 1].0;
+        let count = runs.iter().rev().take_while(|(run_level, _)| *run_level == level).count();
+        if count < MAX_MERGED_RUNS {
+            return Ok(());
+        }
+        let group = runs.split_off(runs.len() - count).into_iter().map(|(_, run)| run).collect();
+        let mut run = CsvWriter::new(BufWriter::new(temp_file(command)?), command.csv.delimiter);
+        merge(group, order, command.csv.delimiter, &mut run)?;
+        runs.push((level + 1, rewind(run)?));
+    }
+}
+
+/// Merges sorted runs into `writer`
+fn merge<W: Write>(runs: Vec<File>, order: &Order, delimiter: char, writer: &mut CsvWriter<W>) -> Result<(), Error> {
+    let mut readers: Vec<_> = runs.into_iter().map(|run| FileRead::from_reader(run).records(delimiter)).collect();
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    // The keys were converted once already, the rows of the errors are not known anymore
+    let next = |run: usize, record: Vec<String>| -> Result<Head, Error> {
+        Ok(Head { entry: Entry { keys: order.keys(&record, 0)?, record }, run, order })
+    };
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(record) = reader.next() {
+            heap.push(next(run, record?)?);
+        }
+    }
+    while let Some(head) = heap.pop() {
+        writer.write_record(&head.entry.record)?;
+        if let Some(record) = readers[head.run].next() {
+            heap.push(next(head.run, record?)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(by: &[&str], column_types: &[&str], memory_limit: usize) -> SortCommand {
+        SortCommand {
+            by: by.iter().map(|s| s.to_string()).collect(),
+            nulls: NullOrder::Last,
+            memory_limit,
+            temp_dir: None,
+            output_path: None,
+            output_delimiter: None,
+            compress: None,
+            csv: CsvMetadata {
+                file: "in-memory.csv".to_string(),
+                column_types: column_types.iter().map(|t| t.to_string()).collect(),
+                ..CsvMetadata::default()
+            },
+        }
+    }
+
+    fn run(data: &str, command: &SortCommand) -> Result<String, Error> {
+        let buffer = FileRead::from_reader(std::io::Cursor::new(data.to_string()));
+        let mut writer = CsvWriter::new(Vec::new(), ',');
+        sort_to(buffer, command, &mut writer)?;
+        Ok(String::from_utf8(writer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let data = "id,score,name\n1,10,b\n2,9,a\n3,,c\n4,10,a\n5,-1,\"x,y\"\n";
+        let mut command = command(&["score:desc", "$3"], &["int", "int"], 1 << 20);
+        assert_eq!(run(data, &command).unwrap(), "id,score,name\n4,10,a\n1,10,b\n2,9,a\n5,-1,\"x,y\"\n3,,c\n");
+        command.nulls = NullOrder::First;
+        command.by = vec!["score".to_string()];
+        assert_eq!(run(data, &command).unwrap(), "id,score,name\n3,,c\n5,-1,\"x,y\"\n2,9,a\n1,10,b\n4,10,a\n");
+        // Untyped numbers are compared as text
+        command.csv.column_types = vec![];
+        assert_eq!(run(data, &command).unwrap(), "id,score,name\n3,,c\n5,-1,\"x,y\"\n1,10,b\n4,10,a\n2,9,a\n");
+        command.csv.has_header = false;
+        command.by = vec!["$1:desc".to_string()];
+        assert_eq!(run("1,a\n3,b\n2,c\n", &command).unwrap(), "3,b\n2,c\n1,a\n");
+    }
+
+    #[test]
+    fn test_external_sort_matches_in_memory() {
+        // Few distinct keys so the stability of the merge is checked
+        let mut data = "key,day,id\n".to_string();
+        for id in 0..3000 {
+            let day = match id % 7 {
+                0 => String::new(),
+                n => format!("2024-0{}-1{}", n, id % 10),
+            };
+            data.push_str(&format!("{},{},{}\n", (id * 7919) % 13, day, id));
+        }
+        let in_memory = command(&["key", "day:desc"], &["int", "date"], usize::MAX);
+        let expected = run(&data, &in_memory).unwrap();
+        // Small budgets spill many runs, more than can be merged at once
+        for memory_limit in [1, 4096, 64 << 10] {
+            let spilled = command(&["key", "day:desc"], &["int", "date"], memory_limit);
+            assert_eq!(run(&data, &spilled).unwrap(), expected, "memory limit {}", memory_limit);
+        }
+    }
+
+    #[test]
+    fn test_sort_errors() {
+        let error = run("a,b\n1,2\n", &command(&["c"], &[], 1 << 20)).unwrap_err();
+        assert!(matches!(error, Error::MissingColumn(column) if column == "c"));
+        let error = run("a,b\n1,2\nx,3\n", &command(&["a"], &["int"], 1 << 20)).unwrap_err();
+        assert!(matches!(error, Error::TypeConversion(e) if e.row == 3));
+        assert_eq!(parse_size("64M"), Ok(64 << 20));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("1.5G").is_err());
+    }
+}
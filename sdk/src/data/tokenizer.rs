@@ -0,0 +1,567 @@
+//! Record tokenizer following the RFC 4180 quoting rules
+//!
+//! A field may be wrapped in double quotes to embed the delimiter, an escaped quote (`""`) or a line break.
+//! Because a quoted line break splits one record across several physical lines, [Records] stitches the lines
+//! yielded by [FileRead::lines](super::manipulation::FileRead::lines) back together before [split_record] breaks
+//! the record into fields. Records are split by jumping from one delimiter or quote to the next with the vectorized
+//! [Scanner](super::scanner::Scanner) rather than inspecting every character.
+//!
+//! The written records are canonical rather than copies of the input: [join_record] quotes only the fields that need
+//! it, so `"1"` is written `1`, and a line break within a quoted field is written `\n` whatever it was in the input,
+//! the lines being read without their terminator.
+//!
+//! # Example
+//! ```
+//! use sdk::data::tokenizer;
+//! let fields = tokenizer::split_record("1,\"a,b\",\"say \"\"hi\"\"\"", ',').unwrap();
+//! assert_eq!(fields, vec!["1", "a,b", "say \"hi\""]);
+//! ```
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+use std::ops::Range;
+use super::scanner::Scanner;
+use crate::Error;
+
+/// Quote character used to wrap fields
+pub const QUOTE: char = '"';
+
+/// Returns `true` when every quoted field in `record` is closed, i.e. the record does not continue on the next line.
+///
+/// A quote only opens a field when the field is blank so far, see [split_record], so counting the quotes is not
+/// enough: `5'10"` is an unquoted field ending with a quote.
+pub fn is_complete(record: &str, delimiter: char) -> bool {
+    let mut state = QuoteState::START;
+    RecordEnds::new(delimiter).find(record.as_bytes(), 0, &mut state);
+    !matches!(state, QuoteState::Quoted { .. })
+}
+
+/// Position within a record relative to its quoted fields, following the rules of [field_ranges]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QuoteState {
+    /// Outside of a quoted field, `blank` when the field is only whitespace so far
+    Field { blank: bool },
+    /// Within a quoted field, `blank` when its content is only whitespace so far
+    Quoted { blank: bool },
+    /// Right after a quote within a quoted field, an escaped quote when a quote follows and the end of the field
+    /// otherwise
+    Closing { blank: bool },
+}
+
+impl QuoteState {
+    /// State at the start of a record
+    pub(crate) const START: QuoteState = QuoteState::Field { blank: true };
+    /// Every state, the candidates at an arbitrary offset of the data
+    pub(crate) const ALL: [QuoteState; 6] = [
+        QuoteState::START,
+        QuoteState::Field { blank: false },
+        QuoteState::Quoted { blank: true },
+        QuoteState::Quoted { blank: false },
+        QuoteState::Closing { blank: true },
+        QuoteState::Closing { blank: false },
+    ];
+}
+
+/// Finds the line breaks ending records, i.e. the line breaks outside of a quoted field
+///
+/// The [Scanner] jumps from one quote or line break to the next, the delimiters are only looked at when a quote may
+/// open a field.
+pub(crate) struct RecordEnds {
+    delimiter: char,
+    breaks: Scanner,
+    quotes: Scanner,
+}
+
+impl RecordEnds {
+    pub(crate) fn new(delimiter: char) -> RecordEnds {
+        RecordEnds { delimiter, breaks: Scanner::new(&[QUOTE as u8, b'\n']), quotes: Scanner::new(&[QUOTE as u8]) }
+    }
+
+    /// Returns the offset of the first line break ending a record in `bytes` after `from`, `state` being the state
+    /// at `from`
+    ///
+    /// `state` is left at the start of the next record, or at the end of `bytes` when no record ends in them so the
+    /// search carries on with the bytes that follow. The bytes must not end in the middle of a character.
+    pub(crate) fn find(&self, bytes: &[u8], from: usize, state: &mut QuoteState) -> Option<usize> {
+        let mut position = from;
+        loop {
+            match *state {
+                QuoteState::Field { blank } => match self.breaks.find(bytes, position) {
+                    Some(next) if bytes[next] == b'\n' => {
+                        *state = QuoteState::START;
+                        return Some(next);
+                    }
+                    Some(quote) => {
+                        *state = match self.is_blank(bytes, position, quote, blank) {
+                            true => QuoteState::Quoted { blank: true },
+                            // Kept as is within an unquoted field
+                            false => QuoteState::Field { blank: false },
+                        };
+                        position = quote + 1;
+                    }
+                    None => {
+                        *state = QuoteState::Field { blank: self.is_blank(bytes, position, bytes.len(), blank) };
+                        return None;
+                    }
+                },
+                QuoteState::Quoted { blank } => match self.quotes.find(bytes, position) {
+                    Some(quote) => {
+                        *state = QuoteState::Closing { blank: blank && is_whitespace(&bytes[position..quote]) };
+                        position = quote + 1;
+                    }
+                    None => {
+                        *state = QuoteState::Quoted { blank: blank && is_whitespace(&bytes[position..]) };
+                        return None;
+                    }
+                },
+                QuoteState::Closing { blank } => match bytes.get(position) {
+                    Some(&byte) if byte == QUOTE as u8 => {
+                        *state = QuoteState::Quoted { blank: false };
+                        position += 1;
+                    }
+                    Some(_) => *state = QuoteState::Field { blank },
+                    None => return None,
+                },
+            }
+        }
+    }
+
+    /// Whether the field is only whitespace at `end`, `blank` telling whether it is at `start`
+    ///
+    /// The bytes in between have no quote nor line break, so walking back over the whitespace either reaches the
+    /// delimiter starting the field or `start`.
+    fn is_blank(&self, bytes: &[u8], start: usize, mut end: usize, blank: bool) -> bool {
+        while end > start {
+            // The last character starts at the last byte that is not a continuation byte
+            let Some(first) = (start..end).rev().take(4).find(|i| bytes[*i] & 0xc0 != 0x80) else {
+                return false;
+            };
+            match std::str::from_utf8(&bytes[first..end]).ok().and_then(|c| c.chars().next()) {
+                Some(c) if c == self.delimiter => return true,
+                Some(c) if c.is_whitespace() => end = first,
+                _ => return false,
+            }
+        }
+        blank
+    }
+}
+
+/// Whether `bytes` are only whitespace, bytes that are not UTF-8 not being whitespace
+fn is_whitespace(bytes: &[u8]) -> bool {
+    match bytes.first() {
+        Some(byte) if byte.is_ascii() && !byte.is_ascii_whitespace() && *byte != 0x0b => false,
+        _ => String::from_utf8_lossy(bytes).chars().all(char::is_whitespace),
+    }
+}
+
+/// Splits a single (possibly multi-line) record into its fields, removing the enclosing quotes and unescaping `""`.
+///
+/// Whitespace between a delimiter and an opening quote is dropped, any other character is kept as is.
+/// # Errors
+/// Returns an [Error::MalformedRecord] error, positioned at the opening quote within `record`, if a quoted field is
+/// never closed.
+pub fn split_record(record: &str, delimiter: char) -> Result<Vec<String>, Error> {
+    let mut fields: Vec<String> = Vec::new();
+    field_ranges(record, delimiter, |range| fields.push(decode_field(&record[range]).into_owned()))
+        .map_err(|quote_start| unterminated(record, quote_start))?;
+    Ok(fields)
+}
+
+/// Error of a quoted field opened at `quote_start` in `record` and never closed
+pub(crate) fn unterminated(record: &str, quote_start: usize) -> Error {
+    Error::MalformedRecord {
+        line: record[..quote_start].matches('\n').count() + 1,
+        byte: quote_start as u64,
+        message: format!("Unterminated quoted field in record: {}", record),
+    }
+}
+
+/// Calls `f` with the byte range of every field of `record`, quotes included, following the rules of [split_record]
+///
+/// The [Scanner] jumps from one delimiter or quote to the next. Returns the offset of the opening quote of a field
+/// that is never closed.
+pub(crate) fn field_ranges(record: &str, delimiter: char, mut f: impl FnMut(Range<usize>)) -> Result<(), usize> {
+    let bytes = record.as_bytes();
+    let mut encoded = [0; 4];
+    let delimiter = delimiter.encode_utf8(&mut encoded).as_bytes();
+    let structural = Scanner::new(&[delimiter[0], QUOTE as u8]);
+    let quotes = Scanner::new(&[QUOTE as u8]);
+    let (mut start, mut position) = (0, 0);
+    // Whether the decoded field is only whitespace so far, a quote then opens a quoted field
+    let mut blank = true;
+    while let Some(next) = structural.find(bytes, position) {
+        blank = blank && record[position..next].chars().all(char::is_whitespace);
+        if bytes[next] == QUOTE as u8 && blank {
+            // The field ends at the first quote not followed by another one, `""` being an escaped quote
+            let mut close = next + 1;
+            loop {
+                match quotes.find(bytes, close) {
+                    Some(quote) if bytes.get(quote + 1) == Some(&(QUOTE as u8)) => close = quote + 2,
+                    Some(quote) => {
+                        close = quote;
+                        break;
+                    }
+                    None => return Err(next),
+                }
+            }
+            blank = record[next + 1..close].chars().all(char::is_whitespace);
+            position = close + 1;
+        } else if bytes[next..].starts_with(delimiter) {
+            f(start..next);
+            position = next + delimiter.len();
+            start = position;
+            blank = true;
+        } else {
+            // A quote within an unquoted field, or a character starting like a multi-byte delimiter, is kept as is
+            let c = record[next..].chars().next().unwrap_or(QUOTE);
+            blank = blank && c.is_whitespace();
+            position = next + c.len_utf8();
+        }
+    }
+    f(start..bytes.len());
+    Ok(())
+}
+
+/// Decodes a raw field located by [field_ranges], borrowing it unless it has escaped quotes or text around its quotes
+pub(crate) fn decode_field(raw: &str) -> Cow<'_, str> {
+    if !raw.contains(QUOTE) {
+        return Cow::Borrowed(raw);
+    }
+    let quoted = raw.trim_start().strip_prefix(QUOTE).and_then(|rest| rest.strip_suffix(QUOTE));
+    if let Some(inner) = quoted.filter(|inner| !inner.contains(QUOTE)) {
+        return Cow::Borrowed(inner);
+    }
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == QUOTE {
+                if chars.peek() == Some(&QUOTE) {
+                    field.push(QUOTE);
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == QUOTE && field.trim().is_empty() {
+            field.clear();
+            in_quotes = true;
+        } else {
+            field.push(c);
+        }
+    }
+    Cow::Owned(field)
+}
+
+/// Joins fields into a single record, quoting the fields that contain the delimiter, a quote or a line break
+pub fn join_record<S: AsRef<str>>(fields: &[S], delimiter: char) -> String {
+    let mut record = String::new();
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            record.push(delimiter);
+        }
+        let field = field.as_ref();
+        if field.contains([delimiter, QUOTE, '\n', '\r']) {
+            record.push(QUOTE);
+            record.push_str(&field.replace(QUOTE, "\"\""));
+            record.push(QUOTE);
+        } else {
+            record.push_str(field);
+        }
+    }
+    record
+}
+
+/// Reads one full record from `reader` into `buffer`, consuming as many physical lines as needed to close
+/// every quoted field of the `delimiter` separated fields. The line terminators are kept.
+///
+/// Returns the number of bytes read, `0` at the end of the input.
+pub fn read_record<R: BufRead>(reader: &mut R, buffer: &mut String, delimiter: char) -> Result<usize, Error> {
+    let ends = RecordEnds::new(delimiter);
+    let mut state = QuoteState::START;
+    let mut total = 0;
+    loop {
+        let start = buffer.len();
+        let read = reader.read_line(buffer)?;
+        if read == 0 {
+            return Ok(total);
+        }
+        total += read;
+        if ends.find(buffer.as_bytes(), start, &mut state).is_some() {
+            return Ok(total);
+        }
+    }
+}
+
+/// Iterator adapter turning physical lines into tokenized records
+///
+/// Lines are joined with `\n` while a quoted field is left open, so a record with embedded line breaks is yielded
+/// as a single `Vec<String>`.
+///
+/// Malformed records and invalid UTF-8 are reported as [Error::MalformedRecord], positioned by counting the lines
+/// read so far and assuming `\n` line terminators.
+pub struct Records<I> {
+    lines: I,
+    delimiter: char,
+    ends: RecordEnds,
+    /// 1-based number of the next line
+    line: usize,
+    /// Offset of the next line
+    byte: u64,
+}
+
+impl<I> Records<I>
+where
+    I: Iterator<Item = Result<String, io::Error>>,
+{
+    /// Wraps an iterator of lines, splitting every record on `delimiter`
+    pub fn new(lines: I, delimiter: char) -> Records<I> {
+        Records { lines, delimiter, ends: RecordEnds::new(delimiter), line: 1, byte: 0 }
+    }
+
+    /// 1-based line number the next record starts at
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    fn next_line(&mut self) -> Option<Result<String, Error>> {
+        match self.lines.next()? {
+            Ok(line) => {
+                self.line += 1;
+                self.byte += line.len() as u64 + 1;
+                Some(Ok(line))
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Some(Err(Error::MalformedRecord {
+                line: self.line,
+                byte: self.byte,
+                message: e.to_string(),
+            })),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+impl<I> Iterator for Records<I>
+where
+    I: Iterator<Item = Result<String, io::Error>>,
+{
+    type Item = Result<Vec<String>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, byte) = (self.line, self.byte);
+        let mut record = match self.next_line()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        // The lines come without their terminator, the record ends at the one of a line that closes every field
+        let mut state = QuoteState::START;
+        let mut start = 0;
+        while self.ends.find(record.as_bytes(), start, &mut state).is_none()
+            && self.ends.find(b"\n", 0, &mut state).is_none()
+        {
+            match self.next_line() {
+                Some(Ok(line)) => {
+                    record.push('\n');
+                    start = record.len();
+                    record.push_str(&line);
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                // Reported as an unterminated field by `split_record`
+                None => break,
+            }
+        }
+        // Positions of `split_record` are relative to the start of the record
+        Some(split_record(&record, self.delimiter).map_err(|e| match e {
+            Error::MalformedRecord { line: record_line, byte: record_byte, message } => Error::MalformedRecord {
+                line: line + record_line - 1,
+                byte: byte + record_byte,
+                message,
+            },
+            e => e,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(data: &str) -> impl Iterator<Item = Result<String, io::Error>> + '_ {
+        data.lines().map(|line| Ok(line.to_string()))
+    }
+
+    #[test]
+    fn test_split_quoted_fields() {
+        let fields = split_record("1,\"a,b\",2,3", ',').unwrap();
+        assert_eq!(fields, vec!["1", "a,b", "2", "3"]);
+
+        let fields = split_record("\"say \"\"hi\"\"\",,x", ',').unwrap();
+        assert_eq!(fields, vec!["say \"hi\"", "", "x"]);
+    }
+
+    /// Character by character splitting, the tokenizer before the [Scanner]
+    fn reference_split(record: &str, delimiter: char) -> Result<Vec<String>, usize> {
+        let (mut fields, mut field) = (Vec::new(), String::new());
+        let (mut in_quotes, mut quote_start) = (false, 0);
+        let mut chars = record.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            if in_quotes {
+                if c == QUOTE && chars.peek().map(|(_, c)| *c) == Some(QUOTE) {
+                    field.push(QUOTE);
+                    chars.next();
+                } else if c == QUOTE {
+                    in_quotes = false;
+                } else {
+                    field.push(c);
+                }
+            } else if c == delimiter {
+                fields.push(std::mem::take(&mut field));
+            } else if c == QUOTE && field.trim().is_empty() {
+                field.clear();
+                in_quotes = true;
+                quote_start = position;
+            } else {
+                field.push(c);
+            }
+        }
+        match in_quotes {
+            true => Err(quote_start),
+            false => Ok([fields, vec![field]].concat()),
+        }
+    }
+
+    #[test]
+    fn test_split_matches_reference() {
+        let alphabet = ['a', ' ', ',', '"', '\n', 'é', '\t', '¦'];
+        let mut state: u64 = 7;
+        for _ in 0..20000 {
+            let record: String = (0..state % 14).map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                alphabet[(state >> 33) as usize % alphabet.len()]
+            }).collect();
+            for delimiter in [',', '\t', '¦'] {
+                let found = split_record(&record, delimiter).map_err(|e| match e {
+                    Error::MalformedRecord { byte, .. } => byte as usize,
+                    _ => usize::MAX,
+                });
+                assert_eq!(found, reference_split(&record, delimiter), "{:?}", record);
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_unterminated_quote() {
+        let result = split_record("1,\"a,b", ',');
+        assert!(matches!(result, Err(Error::MalformedRecord { line: 1, byte: 2, .. })));
+    }
+
+    #[test]
+    fn test_malformed_record_position() {
+        let data = "key,val\n1,a\n2,\"open\nstill open";
+        let error = Records::new(lines(data), ',').nth(2).unwrap().unwrap_err();
+        assert!(matches!(error, Error::MalformedRecord { line: 3, byte: 14, .. }));
+    }
+
+    #[test]
+    fn test_records_spanning_lines() {
+        let data = "key,val\n1,\"multi\nline, text\"\n2,plain";
+        let records: Vec<Vec<String>> = Records::new(lines(data), ',').map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1], vec!["1", "multi\nline, text"]);
+        assert_eq!(records[2], vec!["2", "plain"]);
+    }
+
+    #[test]
+    fn test_records_with_unquoted_quotes() {
+        let data = "id,h,n\n1,5'10\",bob\n2,6ft,amy\n3,5ft,joe";
+        let records: Vec<Vec<String>> = Records::new(lines(data), ',').map(|r| r.unwrap()).collect();
+        assert_eq!(records, vec![vec!["id", "h", "n"], vec!["1", "5'10\"", "bob"], vec!["2", "6ft", "amy"], vec!["3", "5ft", "joe"]]);
+        assert!(is_complete("1,5'10\",bob", ','));
+        assert!(!is_complete("1, \"a\"\"b,c", ','));
+
+        let mut reader = io::Cursor::new(data);
+        let mut buffer = String::new();
+        read_record(&mut reader, &mut buffer, ',').unwrap();
+        buffer.clear();
+        read_record(&mut reader, &mut buffer, ',').unwrap();
+        assert_eq!(buffer, "1,5'10\",bob\n");
+    }
+
+    /// Character by character splitting of csv data into records, a line break outside of a quoted field ending a
+    /// record, with the offset of the opening quote of an unterminated field
+    fn reference_records(data: &str, delimiter: char) -> Vec<Result<Vec<String>, usize>> {
+        let (mut records, mut fields, mut field) = (Vec::new(), Vec::new(), String::new());
+        let (mut in_quotes, mut quote_start) = (false, 0);
+        let mut chars = data.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            if in_quotes {
+                if c == QUOTE && chars.peek().map(|(_, c)| *c) == Some(QUOTE) {
+                    field.push(QUOTE);
+                    chars.next();
+                } else if c == QUOTE {
+                    in_quotes = false;
+                } else {
+                    field.push(c);
+                }
+            } else if c == '\n' {
+                fields.push(std::mem::take(&mut field));
+                records.push(Ok(std::mem::take(&mut fields)));
+            } else if c == delimiter {
+                fields.push(std::mem::take(&mut field));
+            } else if c == QUOTE && field.trim().is_empty() {
+                field.clear();
+                in_quotes = true;
+                quote_start = position;
+            } else {
+                field.push(c);
+            }
+        }
+        if in_quotes {
+            records.push(Err(quote_start));
+        } else if !data.is_empty() && !data.ends_with('\n') {
+            fields.push(field);
+            records.push(Ok(fields));
+        }
+        records
+    }
+
+    #[test]
+    fn test_records_match_reference() {
+        let alphabet = ['a', ' ', ',', '"', '\n', 'é', '\t', '¦', '\u{a0}'];
+        let mut state: u64 = 11;
+        for _ in 0..20000 {
+            let data: String = (0..state % 24).map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                alphabet[(state >> 33) as usize % alphabet.len()]
+            }).collect();
+            for delimiter in [',', '\t', '¦'] {
+                let found: Vec<Result<Vec<String>, usize>> = Records::new(lines(&data), delimiter)
+                    .map(|record| record.map_err(|e| match e {
+                        Error::MalformedRecord { byte, .. } => byte as usize,
+                        _ => usize::MAX,
+                    }))
+                    .collect();
+                assert_eq!(found, reference_records(&data, delimiter), "{:?}", data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_join_round_trip() {
+        let fields = vec!["1", "a,b", "say \"hi\"", "two\nlines"];
+        let record = join_record(&fields, ',');
+        assert_eq!(record, "1,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"");
+        assert_eq!(split_record(&record, ',').unwrap(), fields);
+    }
+
+    #[test]
+    fn test_read_record() {
+        let mut reader = io::Cursor::new("\"a\nb\",c\nd,e\n");
+        let mut buffer = String::new();
+        let read = read_record(&mut reader, &mut buffer, ',').unwrap();
+        assert_eq!(buffer, "\"a\nb\",c\n");
+        assert_eq!(read, buffer.len());
+    }
+}
@@ -0,0 +1,126 @@
+//! Provides the [Error] returned by the public functions of the sdk
+//!
+//! Every failure is reported as a variant of [Error] instead of a panic, so applications embedding the sdk can tell
+//! an unreadable file from a missing column or a malformed record and react accordingly.
+//!
+//! # Example
+//! ```
+//! use sdk::Error;
+//! use sdk::data::tokenizer;
+//! match tokenizer::split_record("1,\"a,b", ',') {
+//!     Err(Error::MalformedRecord { line, byte, .. }) => assert_eq!((line, byte), (1, 2)),
+//!     _ => panic!("Expected a malformed record"),
+//! }
+//! ```
+use std::fmt;
+use std::io;
+use std::path::Path;
+use crate::data::schema::ConversionError;
+
+/// Errors returned by the sdk
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the data failed
+    Io(io::Error),
+    /// The glob pattern is invalid, matches no file or a matching path cannot be read
+    Glob(String),
+    /// An argument is invalid, e.g. an unknown column type, a query that is not a valid pattern or a file that is not a csv
+    InvalidArgument(String),
+    /// The `--where` expression is invalid or does not evaluate to a boolean
+    Expression(String),
+    /// A column is not found in the header
+    MissingColumn(String),
+    /// The header of `file` differs from the `expected` header of the first file
+    HeaderMismatch { file: String, expected: String, found: String },
+    /// A record cannot be tokenized, `line` is the 1-based line and `byte` the 0-based offset of the error in the input
+    MalformedRecord { line: usize, byte: u64, message: String },
+    /// A field cannot be converted to its column type
+    TypeConversion(ConversionError),
+}
+
+impl Error {
+    /// Name of the variant in snake case, e.g. `missing_column`, used by machine readable reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::Glob(_) => "glob",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::Expression(_) => "expression",
+            Error::MissingColumn(_) => "missing_column",
+            Error::HeaderMismatch { .. } => "header_mismatch",
+            Error::MalformedRecord { .. } => "malformed_record",
+            Error::TypeConversion(_) => "type_conversion",
+        }
+    }
+
+    /// Maps an error on the file at `path` to an [Error::Io] naming the file, the kind of the error being kept
+    pub(crate) fn file<P: AsRef<Path>>(path: P) -> impl FnOnce(io::Error) -> Error {
+        move |e| Error::Io(io::Error::new(e.kind(), format!("{}: {}", path.as_ref().display(), e)))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Glob(message) => write!(f, "Glob error: {}", message),
+            Error::InvalidArgument(message) => write!(f, "{}", message),
+            Error::Expression(message) => write!(f, "Invalid expression: {}", message),
+            Error::MissingColumn(column) => write!(f, "Column {} not found in the csv file", column),
+            Error::HeaderMismatch { file, expected, found } => write!(
+                f,
+                "Header of {} does not match the first file, expected `{}`, found `{}`",
+                file, expected, found
+            ),
+            Error::MalformedRecord { line, byte, message } => {
+                write!(f, "Malformed record at line {}, byte {}: {}", line, byte, message)
+            }
+            Error::TypeConversion(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::TypeConversion(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Lets the sdk errors flow through [io::Read] and line iterators, errors other than [Error::Io] are
+/// [io::ErrorKind::InvalidData]
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+impl From<ConversionError> for Error {
+    fn from(e: ConversionError) -> Error {
+        Error::TypeConversion(e)
+    }
+}
+
+impl From<glob::PatternError> for Error {
+    fn from(e: glob::PatternError) -> Error {
+        Error::Glob(e.to_string())
+    }
+}
+
+impl From<glob::GlobError> for Error {
+    fn from(e: glob::GlobError) -> Error {
+        Error::Glob(e.to_string())
+    }
+}
@@ -2,21 +2,38 @@
 //! for data manipulation. Current functionality provides filtering of large csv files reducing the I/O overhead from
 //! high-level programming languages like Python
 //! 
-//! # Panics
-//! If the file path is not provided as a command line argument, a panic will result
+//! # Errors
+//! The public functions return an [Error] describing the failure instead of panicking
 
+pub mod compression;
+pub mod error;
 pub mod reader;
 pub mod stdin_parser;
 pub mod data;
 pub mod writer;
 
 use data::manipulation::FileRead;
+pub use error::Error;
 
 /// Loader function that reads a csv file and returns a FileRead enum
-pub fn loader(csv_handler: &reader::CsvMetadata) -> FileRead {
-    if csv_handler.file.contains("*"){
+/// 
+/// The data is read from stdin when the file is `-`, see [reader::STDIN]. Glob patterns, and files read with
+/// [source columns](reader::CsvMetadata::source_columns), go through [reader::glob_reader]. A file is memory mapped by
+/// [reader::mapped_reader] with [reader::CsvMetadata::mmap].
+/// # Errors
+/// Returns an [Error::Io] error naming the file if it cannot be opened and an [Error::Glob] error if the pattern is
+/// invalid.
+pub fn loader(csv_handler: &reader::CsvMetadata) -> Result<FileRead, Error> {
+    if csv_handler.is_stdin() {
+        reader::stdin_reader()
+    }
+    // A plain path is a glob pattern matching a single file, read as such to get the source columns
+    else if csv_handler.file.contains("*") || csv_handler.source_columns {
         reader::glob_reader(csv_handler)
     }
+    else if csv_handler.mmap {
+        reader::mapped_reader(csv_handler)
+    }
     else {
         reader::csv_reader(csv_handler)
     }
@@ -33,11 +50,9 @@ mod tests {
         path.push("test/example/data.csv");
         let csv_handler = reader::CsvMetadata {
             file: path.to_str().unwrap().to_string(),
-            delimiter: ',',
-            has_header: true,
-            column_types: vec!["string".to_string()]
+            ..reader::CsvMetadata::default()
         };
-        let result = super::loader(&csv_handler);
+        let result = super::loader(&csv_handler).unwrap();
         let mut buffer =[0; 3];
         match result {
             FileRead::Reader(mut reader) => {
@@ -48,4 +63,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_loader_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.csv");
+        let mut csv_handler = reader::CsvMetadata {
+            file: path.to_str().unwrap().to_string(),
+            column_types: vec![],
+            ..reader::CsvMetadata::default()
+        };
+        for mmap in [false, true] {
+            csv_handler.mmap = mmap;
+            let Err(Error::Io(e)) = super::loader(&csv_handler) else { panic!("Expected an io error") };
+            assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+            assert!(e.to_string().starts_with(&csv_handler.file), "{}", e);
+        }
+    }
+
 }
@@ -1,9 +1,13 @@
 //! Provide [CsvMetadata] struct and [csv_reader] function to read csv files
-use std::fs::File;
 use clap::Parser;
 use glob::glob;
-use std::io::{SeekFrom, Seek, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use crate::compression::{self, Compression};
+use crate::data::mapped::MappedFile;
 use crate::data::manipulation::FileRead;
+use crate::data::tokenizer;
+use crate::Error;
 
 /// A reader module defines the struct containing metadata of the csv file
 /// # Example
@@ -12,98 +16,317 @@ use crate::data::manipulation::FileRead;
 /// # use sdk::reader::CsvMetadata;
 /// let metadata = CsvMetadata {
 ///     file: "/path/to/file.csv".to_string(),
-///     delimiter: ',',
-///     has_header: true,
-///     column_types: vec!["string".to_string()]
+///     ..CsvMetadata::default()
 /// };
 /// ```
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[clap(about = "CSV constructur")]
 pub struct CsvMetadata {
-    #[clap(long)]
+    #[clap(long, help = "Path or glob pattern of the csv file(s), `-` reads from stdin")]
     pub file: String,
-    #[clap(long, default_value = ",")]
+    #[clap(long, default_value = ",", value_parser = parse_delimiter, help = "Field delimiter, a single character or one of `\\t`, `tab`")]
     pub delimiter: char,
     #[clap(long, action, default_value = "true")]
     pub has_header: bool,
-    #[clap(long, value_delimiter= ',', default_value = "string")]
+    #[clap(long, value_delimiter= ',', default_value = "string", help = "Types of the columns by position comma separated: string, int, float, bool, date, datetime, decimal")]
     pub column_types: Vec<String>,
+    #[clap(long, action, help = "Combine files with different columns, missing columns are left empty")]
+    pub union: bool,
+    #[clap(long, action, help = "Append the `_source_file` and `_source_line` columns to every record")]
+    pub source_columns: bool,
+    /// Memory maps the file, see [mapped_reader]. The concurrent filters of
+    /// [parallel::filter](crate::data::parallel::filter) read byte ranges of the file and reject it.
+    #[clap(long, action, help = "Memory map the file so the filters read its records without copying them, not supported with --threads")]
+    pub mmap: bool,
 }
 
-impl CsvMetadata {
-    /// Instanstiate `self` and return clone of the CsvMetadata struct
-    pub fn clone(&self) -> CsvMetadata {
+/// Value of [CsvMetadata::file] reading the csv data from stdin
+pub const STDIN: &str = "-";
+
+/// Column holding the path of the file a record was read from, see [CsvMetadata::source_columns]
+pub const SOURCE_FILE: &str = "_source_file";
+
+/// Column holding the 1-based line a record starts at in its file, see [CsvMetadata::source_columns]
+pub const SOURCE_LINE: &str = "_source_line";
+
+/// Defaults of the command line options, the file being left empty
+impl Default for CsvMetadata {
+    fn default() -> CsvMetadata {
         CsvMetadata {
-            file: self.file.clone(),
-            delimiter: self.delimiter,
-            has_header: self.has_header,
-            column_types: self.column_types.clone()
+            file: String::new(),
+            delimiter: ',',
+            has_header: true,
+            column_types: vec!["string".to_string()],
+            union: false,
+            source_columns: false,
+            mmap: false,
         }
     }
+}
+
+impl CsvMetadata {
+    /// Returns `true` when the csv data is read from stdin, see [STDIN]
+    pub fn is_stdin(&self) -> bool {
+        self.file == STDIN
+    }
 
     /// Validate the csv file based on the file extension and headers for multiple files
     /// 
-    /// The [CsvMetadata] struct is validated based on the file extension matching the `*.csv` extention.
+    /// The [CsvMetadata] struct is validated based on the file extension matching the `*.csv` extention, optionally followed by
+    /// a [compression](crate::compression::Compression) extension such as `*.csv.gz`.
     /// Additionally, for glob files the headers are validated to ensure that all files have the same headers. Otherwise, the concatenation
     /// of the files will result in a misaligned data structure. In other words, there will be shifts to the schema of the data.
+    /// Files with different columns are valid in [CsvMetadata::union] mode, the columns being aligned by [glob_reader].
+    /// 
+    /// Data read from stdin has neither an extension nor other files to compare with and is always valid.
     /// 
     /// # Note
     /// The [CsvMetadata::validate] function is a wrapper for two private functions **validate_extension** and **validate_multifile_header**
     /// If there are a large number of files, the validation process may take some time. This is caused by the need to read the headers of all files
     /// to check for consistency.
-    /// 
-    pub fn validate(&self)-> bool {
-        if self.validate_extension() && self.validate_multifile_header() {
-            return true;
-        }
-        return false;
-    }
-
-    fn validate_extension(&self) -> bool {
-        let ext = self.file.split('.').last().unwrap();
-        if ext == "csv" {
-            return true;
-        }
-        return false;
-    }
-
-    fn validate_multifile_header(&self) -> bool {
-        let mut header_list: Vec<String> = Vec::new();
-        if self.has_header {
-            for entry in glob(&self.file).expect("Failed to read glob pattern") {
-                match entry {
-                    Ok(path) => {
-                        let file_base: File = File::open(&path).expect("Error opening file");
-                        let mut reader = BufReader::new(file_base);
-                        let mut buffer: String = String::new();
-                        reader.read_line(&mut buffer).expect("Unable to read file to buffer");
-                        header_list.push(buffer);
-                    }
-                    Err(e) => eprintln!("{:?}", e),
+    /// # Errors
+    /// Returns an [Error::InvalidArgument] error if the file is not a csv file, an [Error::HeaderMismatch] error if the headers
+    /// differ and an [Error::Glob] error if the pattern is invalid or matches no file.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.is_stdin() {
+            return Ok(());
+        }
+        self.validate_extension()?;
+        self.validate_multifile_header()
+    }
+
+    fn validate_extension(&self) -> Result<(), Error> {
+        match compression::strip_extension(&self.file).rsplit_once('.') {
+            Some((_, "csv")) => Ok(()),
+            _ => Err(Error::InvalidArgument(format!("{} is not a csv file", self.file))),
+        }
+    }
+
+    fn validate_multifile_header(&self) -> Result<(), Error> {
+        if !self.has_header {
+            eprintln!("No headers based on metadata");
+            return Ok(());
+        }
+        let mut first_header: Option<Vec<String>> = None;
+        let mut matched = false;
+        for entry in glob(&self.file)? {
+            let path = entry?;
+            matched = true;
+            if self.union {
+                continue;
+            }
+            let mut reader = BufReader::new(compression::open(&path)?);
+            let Some((header, _)) = read_header(&mut reader, self.delimiter)? else { continue };
+            match &first_header {
+                None => first_header = Some(header),
+                Some(expected) => {
+                    strict_column_order(expected, &header, self.delimiter, &path.display().to_string())?;
                 }
             }
+        }
+        match matched {
+            true => Ok(()),
+            false => Err(Error::Glob(format!("No file matches {}", self.file))),
+        }
+    }
+}
 
-            let mut header_iter = header_list.iter();
-            let first_header = header_iter.next().unwrap();
-            for header in header_iter {
-                if first_header != header {
-                    return false
-                }
+/// Reads and normalizes the header record of a file along with the number of lines it spans, `None` for an empty file
+///
+/// The header is compared by its column names, so a byte order mark, the line terminator, the quotes and the
+/// whitespace around the names are dropped.
+fn read_header<R: BufRead>(reader: &mut R, delimiter: char) -> Result<Option<(Vec<String>, usize)>, Error> {
+    let mut buffer = String::new();
+    if tokenizer::read_record(reader, &mut buffer, delimiter)? == 0 {
+        return Ok(None);
+    }
+    let record = buffer.strip_prefix('\u{feff}').unwrap_or(&buffer).trim_end_matches(['\r', '\n']);
+    let fields = tokenizer::split_record(record, delimiter)?;
+    let lines = record.matches('\n').count() + 1;
+    Ok(Some((fields.iter().map(|field| field.trim().to_string()).collect(), lines)))
+}
+
+/// Position in `found` of every column of `expected`, `None` when the columns are already in order
+fn column_order(expected: &[String], found: &[String]) -> Option<Vec<Option<usize>>> {
+    if expected == found {
+        return None;
+    }
+    Some(expected.iter().map(|column| found.iter().position(|name| name == column)).collect())
+}
+
+/// Same as [column_order] for headers that must have the same columns, possibly in a different order
+///
+/// # Errors
+/// Returns an [Error::HeaderMismatch] error if the headers do not have the same columns.
+fn strict_column_order(expected: &[String], found: &[String], delimiter: char, file: &str) -> Result<Option<Vec<Option<usize>>>, Error> {
+    let order = column_order(expected, found);
+    match &order {
+        Some(positions) if expected.len() != found.len() || positions.contains(&None) => Err(Error::HeaderMismatch {
+            file: file.to_string(),
+            expected: tokenizer::join_record(expected, delimiter),
+            found: tokenizer::join_record(found, delimiter),
+        }),
+        _ => Ok(order),
+    }
+}
+
+/// Superset of the columns of the files at `paths`, in the order they first appear
+fn union_header(paths: &[PathBuf], delimiter: char) -> Result<Vec<String>, Error> {
+    let mut columns: Vec<String> = Vec::new();
+    for path in paths {
+        let mut reader = BufReader::new(compression::open(path)?);
+        for column in read_header(&mut reader, delimiter)?.map(|(header, _)| header).unwrap_or_default() {
+            if !columns.contains(&column) {
+                columns.push(column);
             }
-            return true
         }
-        else {
-            println!("No headers based on metadata");
-            return true
+    }
+    Ok(columns)
+}
+
+/// Origin of the records of a file for the [SOURCE_FILE] and [SOURCE_LINE] columns
+struct Source {
+    file: String,
+    /// Lines before the first record, i.e. the lines of the header
+    offset: usize,
+}
+
+/// Rewrites every record of a file: the fields are reordered following `order`, columns missing from the file being
+/// left empty, and the `source` columns are appended
+fn rewrite(lines: Lines, delimiter: char, order: Option<Vec<Option<usize>>>, source: Option<Source>) -> Lines {
+    let mut records = tokenizer::Records::new(lines, delimiter);
+    Box::new(std::iter::from_fn(move || {
+        let line = records.line();
+        let mut fields = match records.next()? {
+            Ok(fields) => fields,
+            Err(e) => return Some(Err(e.into())),
+        };
+        if let Some(order) = &order {
+            fields = order
+                .iter()
+                .map(|position| position.and_then(|i| fields.get(i).cloned()).unwrap_or_default())
+                .collect();
+        }
+        if let Some(source) = &source {
+            fields.push(source.file.clone());
+            fields.push((source.offset + line).to_string());
+        }
+        Some(Ok(tokenizer::join_record(&fields, delimiter)))
+    }))
+}
+
+/// Parses a delimiter command line argument
+///
+/// Accepts any single character, plus the `\t` and `tab` spellings for tab-separated files which are awkward to pass from a shell.
+/// # Example
+/// ```
+/// # use sdk::reader::parse_delimiter;
+/// assert_eq!(parse_delimiter("|"), Ok('|'));
+/// assert_eq!(parse_delimiter("\\t"), Ok('\t'));
+/// ```
+pub fn parse_delimiter(arg: &str) -> Result<char, String> {
+    match arg {
+        "\\t" | "tab" => Ok('\t'),
+        _ => {
+            let mut chars = arg.chars();
+            match (chars.next(), chars.next()) {
+                (Some(delimiter), None) if delimiter != tokenizer::QUOTE => Ok(delimiter),
+                _ => Err(format!("Invalid delimiter `{}`, expected a single character other than a quote", arg)),
+            }
         }
     }
 }
 
-/// Reads a csv file and returns a `BufReader<File`
-pub fn csv_reader(csv_struct: &CsvMetadata) -> FileRead{
-    let f = File::open(&csv_struct.file).expect("Error opening file");
-    FileRead::Reader(BufReader::new(f))
+/// Reads a csv file and returns a [FileRead::Reader]
+/// 
+/// Compressed files (gzip, zstd, bzip2, xz) are decompressed on the fly, see [compression::open].
+pub fn csv_reader(csv_struct: &CsvMetadata) -> Result<FileRead, Error> {
+    let f = compression::open(&csv_struct.file)?;
+    Ok(FileRead::from_reader(f))
+}
+
+/// Memory maps a csv file and returns a [FileRead::Mapped], see [CsvMetadata::mmap]
+///
+/// Compressed files cannot be read in place and are decompressed on the fly by [csv_reader] instead.
+pub fn mapped_reader(csv_struct: &CsvMetadata) -> Result<FileRead, Error> {
+    let file = MappedFile::open(&csv_struct.file)?;
+    if Compression::from_path(&csv_struct.file).is_some() || Compression::from_magic(file.as_bytes()) != Compression::None {
+        return csv_reader(csv_struct);
+    }
+    Ok(FileRead::Mapped(file))
+}
+
+/// Reads csv data from stdin, e.g. the output of `cat big.csv`. Compressed data is detected from its magic bytes.
+pub fn stdin_reader() -> Result<FileRead, Error> {
+    let f = compression::decompress(io::stdin())?;
+    Ok(FileRead::from_reader(f))
+}
+
+/// Lines of a file yielded by [glob_reader]
+pub(crate) type Lines = Box<dyn Iterator<Item = Result<String, io::Error>>>;
+
+/// Paths matching the glob pattern of [CsvMetadata::file], sorted alphabetically
+pub(crate) fn glob_paths(csv_struct: &CsvMetadata) -> Result<Vec<PathBuf>, Error> {
+    Ok(glob(&csv_struct.file)?.collect::<Result<Vec<PathBuf>, glob::GlobError>>()?)
+}
+
+/// Header shared by the files at `paths`, the header of the first non empty file or, in [CsvMetadata::union] mode,
+/// the superset of the columns. `None` when the files have no header.
+pub(crate) fn common_header(paths: &[PathBuf], csv_struct: &CsvMetadata) -> Result<Option<Vec<String>>, Error> {
+    if !csv_struct.has_header {
+        return Ok(None);
+    }
+    if csv_struct.union {
+        let header = union_header(paths, csv_struct.delimiter)?;
+        return Ok(Some(header).filter(|header| !header.is_empty()));
+    }
+    for path in paths {
+        let mut reader = BufReader::new(compression::open(path)?);
+        if let Some((header, _)) = read_header(&mut reader, csv_struct.delimiter)? {
+            return Ok(Some(header));
+        }
+    }
+    Ok(None)
+}
+
+/// Columns of the combined output of the files, the `header` followed by the source columns when requested
+pub(crate) fn header_columns(csv_struct: &CsvMetadata, header: &[String]) -> Vec<String> {
+    let mut columns = header.to_vec();
+    if csv_struct.source_columns {
+        columns.extend([SOURCE_FILE.to_string(), SOURCE_LINE.to_string()]);
+    }
+    columns
+}
+
+/// Lines of the records of the file at `path` aligned to the `header` returned by [common_header], the header of the
+/// file being dropped. `None` for an empty file.
+///
+/// # Errors
+/// Returns an [Error::HeaderMismatch] error if, outside union mode, the columns of the file differ from `header`.
+pub(crate) fn file_lines(path: &Path, csv_struct: &CsvMetadata, header: Option<&[String]>) -> Result<Option<Lines>, Error> {
+    let delimiter = csv_struct.delimiter;
+    let mut reader = BufReader::new(compression::open(path)?);
+    let mut order = None;
+    let mut offset = 0;
+    if let Some(expected) = header {
+        // The header record may span multiple lines when a column name is quoted
+        let Some((found, lines)) = read_header(&mut reader, delimiter)? else { return Ok(None) };
+        order = match csv_struct.union {
+            true => column_order(expected, &found),
+            false => strict_column_order(expected, &found, delimiter, &path.display().to_string())?,
+        };
+        offset = lines;
+    }
+    let source = match csv_struct.source_columns {
+        true => Some(Source { file: path.display().to_string(), offset }),
+        false => None,
+    };
+    let lines: Lines = Box::new(reader.lines());
+    Ok(Some(match (order, source) {
+        (None, None) => lines,
+        (order, source) => rewrite(lines, delimiter, order, source),
+    }))
 }
 
 /// Reads files matching the given glob pattern and returns an iterator over the contents of these files.
@@ -115,63 +338,56 @@ pub fn csv_reader(csv_struct: &CsvMetadata) -> FileRead{
 /// # Returns
 ///
 /// An iterator over the contents of the files that match the given glob pattern.
+/// Compressed files are decompressed on the fly, see [compression::open].
 ///
-pub fn glob_reader(csv_struct: &CsvMetadata) -> FileRead{
-    let mut first_file:bool = true;
-    let mut readers: Vec<BufReader<File>> = Vec::new();
-    let mut pos: u64 = 0;
-
-    for entry in glob(&csv_struct.file).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                println!("{:?}", path.display());
-                if first_file {
-                    let file_base: File = File::open(&path).expect("Error opening file");
-                    let reader = BufReader::new(file_base);
-                    readers.push(reader);
-                    
-                    let file_temp: File = File::open(&path).expect("Error opening file");
-                    let mut temp_reader = BufReader::new(file_temp);
-                    let mut temp_buffer: String = String::new();
-                    temp_reader.read_line(&mut temp_buffer).expect("Unable to read temp file to buffer");
-                    pos = temp_buffer.len() as u64;
-                    first_file = false;
-                }
-                else {
-                    let mut file_cont: File = File::open(&path).expect("Error opening file");
-                    let _ = file_cont.seek(SeekFrom::Start(pos));
-                    let reader = BufReader::new(file_cont);
-                    readers.push(reader);
-                }
-            
-            }
-            Err(e) => eprintln!("{:?}", e),
+/// When [CsvMetadata::has_header] is set, the header record of every file is parsed and dropped, only the header of the
+/// first file is kept. Headers are compared by column names, ignoring byte order marks, line terminators, quotes and
+/// whitespace. A file with the same columns in a different order has its fields reordered to the first header.
+///
+/// In [CsvMetadata::union] mode the files may have different columns: the header is the superset of the columns in
+/// the order they first appear, the fields of every file are reordered by name and missing columns are left empty,
+/// which the [Schema](crate::data::schema::Schema) reads as null.
+///
+/// With [CsvMetadata::source_columns] the [SOURCE_FILE] and [SOURCE_LINE] columns are appended to every record, so a
+/// row can be traced back to the file and line it was read from.
+/// # Errors
+/// Returns an [Error::Glob] error if the pattern is invalid or a matching path cannot be read, an [Error::Io]
+/// error if a file cannot be opened and, outside union mode, an [Error::HeaderMismatch] error if the columns of a file differ.
+pub fn glob_reader(csv_struct: &CsvMetadata) -> Result<FileRead, Error> {
+    let paths = glob_paths(csv_struct)?;
+    let header = common_header(&paths, csv_struct)?;
+    let mut files: Vec<Lines> = Vec::new();
+
+    if let Some(header) = &header {
+        let record = tokenizer::join_record(&header_columns(csv_struct, header), csv_struct.delimiter);
+        files.push(Box::new(std::iter::once(Ok(record))));
+    }
+    for path in &paths {
+        if let Some(lines) = file_lines(path, csv_struct, header.as_deref())? {
+            files.push(lines);
         }
     }
-    // Combine the readers into a single iterator
-    return FileRead::Iterator(Box::new(readers.into_iter().flat_map(|reader| reader.lines())))
+    // Combine the files into a single iterator
+    Ok(FileRead::Iterator(Box::new(files.into_iter().flatten())))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     fn constructer() -> CsvMetadata {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR")); //crate root
         path.push("test/*/*.csv");
-        return CsvMetadata {
+        CsvMetadata {
             file: path.to_str().unwrap().to_string(),
-            delimiter: ',',
-            has_header: true,
-            column_types: vec!["string".to_string()]
+            ..CsvMetadata::default()
         }
     }
     #[test]
     fn test_glob_reader_multiple_csv() {
         let csv_handler: CsvMetadata = constructer();
 
-        let result = glob_reader(&csv_handler);
+        let result = glob_reader(&csv_handler).unwrap();
         match result {
             FileRead::Iterator(iter) => {
                 assert!(iter.count() > 0);
@@ -183,6 +399,117 @@ mod tests {
     #[test]
     fn test_validation() {
         let csv_handler: CsvMetadata = constructer();
-        assert!(csv_handler.validate());
+        assert!(csv_handler.validate().is_ok());
+    }
+
+    /// Writes `files` to a fresh directory, removed when dropped, and returns the metadata of the `*.csv` pattern in it
+    fn temp_glob(files: &[(&str, &str)]) -> (tempfile::TempDir, CsvMetadata) {
+        let dir = tempfile::tempdir().unwrap();
+        for (file, content) in files {
+            std::fs::write(dir.path().join(file), content).unwrap();
+        }
+        let mut csv_handler: CsvMetadata = constructer();
+        csv_handler.file = dir.path().join("*.csv").to_str().unwrap().to_string();
+        (dir, csv_handler)
+    }
+
+    fn read_lines(csv_handler: &CsvMetadata) -> Result<Vec<String>, io::Error> {
+        glob_reader(csv_handler).unwrap().lines().collect()
+    }
+
+    #[test]
+    fn test_glob_reader_normalized_headers() {
+        let (_dir, csv_handler) = temp_glob(&[
+            ("a.csv", "\u{feff}key,val\n1,a\n"),
+            ("b.csv", "key,val\r\n2,b\r\n"),
+            ("c.csv", "\"key\", val \n3,c"),
+            ("d.csv", "key,val\n1234,\"multi\nline\"\n"),
+        ]);
+        assert_eq!(read_lines(&csv_handler).unwrap(), vec!["key,val", "1,a", "2,b", "3,c", "1234,\"multi", "line\""]);
+    }
+
+    #[test]
+    fn test_glob_reader_aligns_columns() {
+        let (_dir, csv_handler) = temp_glob(&[
+            ("a.csv", "key,val\n1,a\n"),
+            ("b.csv", "val,key\n\"b,c\",2\n"),
+        ]);
+        assert_eq!(read_lines(&csv_handler).unwrap(), vec!["key,val", "1,a", "2,\"b,c\""]);
+    }
+
+    #[test]
+    fn test_glob_reader_header_mismatch() {
+        let (_dir, csv_handler) = temp_glob(&[
+            ("a.csv", "key,val\n1,a\n"),
+            ("b.csv", "key,value\n2,b\n"),
+        ]);
+        assert!(matches!(glob_reader(&csv_handler), Err(Error::HeaderMismatch { .. })));
+    }
+
+    #[test]
+    fn test_glob_reader_union() {
+        let (_dir, mut csv_handler) = temp_glob(&[
+            ("2024-01.csv", "id,amount\n1,10\n"),
+            ("2024-02.csv", "amount,id,currency\n20,2,EUR\n"),
+            ("2024-03.csv", "id,note\n3,\"late, paid\"\n"),
+        ]);
+        csv_handler.union = true;
+        assert!(csv_handler.validate().is_ok());
+        assert_eq!(read_lines(&csv_handler).unwrap(), vec![
+            "id,amount,currency,note",
+            "1,10,,",
+            "2,20,EUR,",
+            "3,,,\"late, paid\"",
+        ]);
+        csv_handler.union = false;
+        assert!(matches!(glob_reader(&csv_handler), Err(Error::HeaderMismatch { .. })));
+    }
+
+    #[test]
+    fn test_glob_reader_source_columns() {
+        let (_dir, mut csv_handler) = temp_glob(&[
+            ("a.csv", "key,val\n1,\"multi\nline\"\n2,b\n"),
+            ("b.csv", "val,key\nc,3\n"),
+        ]);
+        csv_handler.source_columns = true;
+        let dir = csv_handler.file.trim_end_matches("*.csv").to_string();
+        assert_eq!(read_lines(&csv_handler).unwrap(), vec![
+            "key,val,_source_file,_source_line".to_string(),
+            format!("1,\"multi\nline\",{}a.csv,2", dir),
+            format!("2,b,{}a.csv,4", dir),
+            format!("3,c,{}b.csv,2", dir),
+        ]);
+    }
+
+    #[test]
+    fn test_header_mismatch() {
+        let (_dir, mut csv_handler) = temp_glob(&[
+            ("a.csv", "key,val\n1,a\n"),
+            ("b.csv", "key,value\n2,b\n"),
+        ]);
+        match csv_handler.validate() {
+            Err(Error::HeaderMismatch { expected, found, .. }) => assert_eq!((expected.as_str(), found.as_str()), ("key,val", "key,value")),
+            other => panic!("Expected a header mismatch, found {:?}", other),
+        }
+        csv_handler.file = csv_handler.file.replace("*.csv", "missing*.csv");
+        assert!(matches!(csv_handler.validate(), Err(Error::Glob(_))));
+    }
+
+    #[test]
+    fn test_compressed_extension_validation() {
+        let mut csv_handler: CsvMetadata = constructer();
+        csv_handler.has_header = false;
+        csv_handler.file = "extract.csv.gz".to_string();
+        assert!(csv_handler.validate().is_ok());
+        csv_handler.file = "extract.json.gz".to_string();
+        assert!(matches!(csv_handler.validate(), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_stdin_validation() {
+        let mut csv_handler: CsvMetadata = constructer();
+        csv_handler.file = STDIN.to_string();
+        assert!(csv_handler.is_stdin());
+        assert!(csv_handler.validate().is_ok());
     }
 }
@@ -20,7 +20,7 @@ pub mod argparser {
             }
             panic!("\x1b[0;31mRuntime Panic:\x1b[0m {} --file /path/to/file/ \n\x1b[0;32mHelp:\x1b[0m {} <--help> for more details", args[0], args[0]);
         }
-        return args
+        args
     }
 
     pub fn parser(args:Vec<String>) -> super::HashMap<String, String> {
@@ -1,22 +1,82 @@
-//! Provides [csv_writer] function to write a csv file to disk
-use std::io::Write;
+//! Provides the streaming [CsvWriter] to write a csv file to disk
+use std::io::{self, BufWriter, Write};
 use std::fs::File;
+use crate::compression::{Compression, Encoder};
+use crate::data::tokenizer;
+use crate::Error;
 
-/// Function to write a csv file to disk
-/// 
-/// # TODO:
-/// When writing the file, the function includes a newline character at the EOF. 
-pub fn csv_writer(output_path: String, writer: Vec<Vec<String>>) -> Result<String, std::io::Error> {
-    let file = File::create(output_path);
-    match file {
-        Ok(mut obj) => {
-            for row in writer {
-                let row_str: Vec<String> = row.iter().map(|s| s.to_string()).collect();
-                let _ = obj.write_all(row_str.join(",").as_bytes());
-                let _ = obj.write_all(b"\n");
-        }
-        },
-        Err(e) => return Err(e),
-    }
-    Ok(String::from("SUCCESS"))
-}
\ No newline at end of file
+/// Streaming csv writer, records are written as soon as they are produced so memory stays constant
+///
+/// # Example
+/// ```
+/// # use sdk::writer::CsvWriter;
+/// let mut writer = CsvWriter::new(Vec::new(), ',');
+/// writer.write_record(&["1", "a,b"]).unwrap();
+/// assert_eq!(writer.into_inner(), b"1,\"a,b\"\n");
+/// ```
+pub struct CsvWriter<W: Write> {
+    inner: W,
+    delimiter: char,
+}
+
+impl CsvWriter<BufWriter<File>> {
+    /// Creates (or truncates) the file at `output_path` and wraps it in a buffered writer
+    pub fn create(output_path: &str, delimiter: char) -> Result<CsvWriter<BufWriter<File>>, Error> {
+        let file = File::create(output_path).map_err(Error::file(output_path))?;
+        Ok(CsvWriter::new(BufWriter::new(file), delimiter))
+    }
+}
+
+/// Output of a command opened by [CsvWriter::open]
+pub type Output = Encoder<Box<dyn Write>>;
+
+impl CsvWriter<Output> {
+    /// Opens the output of a command: the file at `output_path`, or stdout when no path is given so the
+    /// results can be piped into other tools
+    ///
+    /// The output is compressed with `compression`, by default the compression is chosen from the extension of
+    /// `output_path` (e.g. `extract.csv.gz`). Call [CsvWriter::finish] to complete the compressed stream.
+    pub fn open(output_path: Option<&str>, delimiter: char, compression: Option<Compression>) -> Result<CsvWriter<Output>, Error> {
+        let inner: Box<dyn Write> = match output_path {
+            Some(path) => Box::new(BufWriter::new(File::create(path).map_err(Error::file(path))?)),
+            None => Box::new(BufWriter::new(io::stdout().lock())),
+        };
+        let compression = compression
+            .or_else(|| output_path.and_then(Compression::from_path))
+            .unwrap_or(Compression::None);
+        Ok(CsvWriter::new(compression.encoder(inner)?, delimiter))
+    }
+
+    /// Writes the end of the compressed stream and flushes the output
+    pub fn finish(self) -> Result<(), Error> {
+        Ok(self.inner.finish()?.flush()?)
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Wraps any writer, fields are joined by `delimiter`
+    pub fn new(inner: W, delimiter: char) -> CsvWriter<W> {
+        CsvWriter { inner, delimiter }
+    }
+
+    /// Writes the fields of a record followed by a newline, quoting them by [tokenizer::join_record] when needed
+    pub fn write_record<S: AsRef<str>>(&mut self, fields: &[S]) -> Result<(), Error> {
+        self.inner.write_all(tokenizer::join_record(fields, self.delimiter).as_bytes())?;
+        Ok(self.inner.write_all(b"\n")?)
+    }
+
+    /// Writes records already serialized by a [CsvWriter] with the same delimiter
+    pub fn write_raw(&mut self, records: &[u8]) -> Result<(), Error> {
+        Ok(self.inner.write_all(records)?)
+    }
+
+    /// Flushes the buffered records
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.inner.flush()?)
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}